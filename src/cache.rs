@@ -0,0 +1,103 @@
+//! A cache of crawled feeds, used to skip crawling a server again while tweaking the settings.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::SyncError, opds::Feed};
+
+/// The directory the feed of every server is cached in.
+const FEED_CACHE_PATH: &str = "Cache";
+
+/// Every page of a server's feed as of its last complete crawl.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FeedCache {
+    /// When the crawl finished.
+    pub crawled: DateTime<Utc>,
+    /// The server URL the feed was crawled from, so changing it invalidates the cache.
+    pub url: String,
+    /// The index of the first entry of every page in `feed`, with the page's URL.
+    pub pages: Vec<(usize, String)>,
+    /// The entries of every page merged into one feed.
+    pub feed: Feed,
+}
+
+impl FeedCache {
+    /// The path the feed of the server with the file name `name` is cached at.
+    pub fn path(name: &str) -> PathBuf {
+        Path::new(FEED_CACHE_PATH).join(format!("{}.json", name))
+    }
+
+    /// Load the feed cached at `path` if it was crawled from `url` less than `ttl` ago. A missing,
+    /// stale or unreadable cache is ignored, as the feed can always be crawled again.
+    pub fn load<P: AsRef<Path>>(path: P, url: &str, ttl: Duration) -> Option<Self> {
+        let s = fs::read_to_string(path).ok()?;
+        let cache: FeedCache = serde_json::from_str(&s).ok()?;
+        let age = Utc::now()
+            .signed_duration_since(cache.crawled)
+            .to_std()
+            .ok()?;
+        (cache.url == url && age < ttl).then_some(cache)
+    }
+
+    /// Write the cache to `path`, creating the cache directory if needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SyncError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| {
+                SyncError::io(format!("can't create directory {}", parent.display()), err)
+            })?;
+        }
+
+        let s = serde_json::to_string(self)
+            .map_err(io::Error::from)
+            .map_err(|err| {
+                SyncError::io(format!("can't serialize feed for {}", path.display()), err)
+            })?;
+        fs::write(path, s)
+            .map_err(|err| SyncError::io(format!("can't write file {}", path.display()), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opds::read_feed;
+
+    /// Test that a cached feed is only loaded while it's fresh and for the URL it was crawled
+    /// from, with its entries intact.
+    #[test]
+    fn load_cached_feed() {
+        let directory =
+            std::env::temp_dir().join(format!("plato-opds-feed-cache-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("nested").join("calibre.json");
+
+        let cache = FeedCache {
+            crawled: Utc::now(),
+            url: "https://books.example.net/opds".into(),
+            pages: vec![(0, "https://books.example.net/opds".into())],
+            feed: read_feed(include_str!("../tests/feed.xml").as_bytes()).unwrap(),
+        };
+        cache.save(&path).unwrap();
+
+        let ttl = Duration::from_secs(60);
+        let loaded = FeedCache::load(&path, &cache.url, ttl).unwrap();
+        assert_eq!(loaded.pages, cache.pages);
+        assert_eq!(
+            serde_json::to_value(&loaded.feed).unwrap(),
+            serde_json::to_value(&cache.feed).unwrap()
+        );
+        assert!(FeedCache::load(&path, "https://books.example.net/other", ttl).is_none());
+        assert!(FeedCache::load(&path, &cache.url, Duration::ZERO).is_none());
+        assert!(FeedCache::load(directory.join("missing.json"), &cache.url, ttl).is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
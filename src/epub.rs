@@ -0,0 +1,504 @@
+//! Embeds the metadata of OPDS entries into the OPF package document of downloaded EPUBs.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{error::SyncError, opds::Entry};
+
+/// The path of the file in an EPUB listing where its package document is.
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// The namespace of the Dublin Core elements package documents describe books with.
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// Write the title, authors and series of `entry`, along with its `description`, into the package
+/// document of the EPUB at `path`, returning the size of the rewritten EPUB. Metadata the entry
+/// doesn't have is left as the EPUB has it.
+///
+/// The EPUB is rewritten to a temporary file that only replaces it once it's complete, so the
+/// original is kept if its structure isn't what's expected.
+pub fn embed_metadata(
+    path: &Path,
+    entry: &Entry,
+    description: Option<&str>,
+) -> Result<u64, SyncError> {
+    let mut embedded_path = path.as_os_str().to_owned();
+    embedded_path.push(".embedded");
+    let embedded_path = PathBuf::from(embedded_path);
+
+    let embed = || {
+        let what = format!("EPUB {}", path.display());
+        let archive_error = |err| SyncError::parse(&what, err);
+        let file = File::open(path)
+            .map_err(|err| SyncError::io(format!("can't open {}", path.display()), err))?;
+        let mut archive = ZipArchive::new(file).map_err(archive_error)?;
+
+        let opf_path =
+            package_path(&read_file(&mut archive, CONTAINER_PATH)?).ok_or_else(|| {
+                SyncError::parse(&what, "the container doesn't list a package document")
+            })?;
+        let opf = rewrite_package(&read_file(&mut archive, &opf_path)?, entry, description)
+            .map_err(|err| SyncError::parse(format!("package document {}", opf_path), err))?;
+
+        let write_error = |err| {
+            SyncError::io(
+                format!("can't write file {}", embedded_path.display()),
+                std::io::Error::from(err),
+            )
+        };
+        let embedded = File::create(&embedded_path).map_err(|err| {
+            SyncError::io(
+                format!("can't create file {}", embedded_path.display()),
+                err,
+            )
+        })?;
+        let mut writer = ZipWriter::new(embedded);
+
+        // Every other file is copied as is, which keeps the uncompressed `mimetype` file first.
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i).map_err(archive_error)?;
+            if file.name() == opf_path {
+                let options =
+                    FileOptions::default().compression_method(CompressionMethod::Deflated);
+                writer
+                    .start_file(opf_path.as_str(), options)
+                    .map_err(write_error)?;
+                writer
+                    .write_all(opf.as_bytes())
+                    .map_err(|err| SyncError::io("can't write package document", err))?;
+            } else {
+                writer.raw_copy_file(file).map_err(write_error)?;
+            }
+        }
+        writer.finish().map_err(write_error)?;
+
+        fs::rename(&embedded_path, path)
+            .map_err(|err| SyncError::io(format!("can't replace {}", path.display()), err))?;
+        fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .map_err(|err| SyncError::io(format!("can't read {}", path.display()), err))
+    };
+
+    embed().inspect_err(|_| {
+        fs::remove_file(&embedded_path).ok();
+    })
+}
+
+/// Read the file at `name` in the EPUB `archive` as text.
+fn read_file(archive: &mut ZipArchive<File>, name: &str) -> Result<String, SyncError> {
+    let mut file = archive
+        .by_name(name)
+        .map_err(|err| SyncError::parse(format!("EPUB file {}", name), err))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(|err| SyncError::parse(format!("EPUB file {}", name), err))?;
+    Ok(contents)
+}
+
+/// The path of the package document listed first in an EPUB's `container`.
+fn package_path(container: &str) -> Option<String> {
+    let mut reader = Reader::from_str(container);
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(start) | Event::Empty(start)
+                if start.local_name().as_ref() == b"rootfile" =>
+            {
+                let path = start.try_get_attribute("full-path").ok()??;
+                return path.unescape_value().ok().map(|path| path.into_owned());
+            }
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+/// Rewrite the package document `opf`, replacing the metadata elements `entry` and `description`
+/// have a value for with ones for their values. EPUB 3 `<meta refines="#id">` elements refining
+/// a replaced element, such as its `file-as`, are dropped along with it.
+fn rewrite_package(
+    opf: &str,
+    entry: &Entry,
+    description: Option<&str>,
+) -> Result<String, quick_xml::Error> {
+    let authors: Vec<&str> = entry
+        .authors
+        .iter()
+        .flatten()
+        .map(|author| author.name.trim())
+        .filter(|name| !name.is_empty())
+        .collect();
+    let title = Some(entry.title.trim()).filter(|title| !title.is_empty());
+    let summary = description
+        .map(str::trim)
+        .filter(|summary| !summary.is_empty());
+    let series = entry.series();
+
+    // Whether an existing element is dropped in favour of the entry's value.
+    let is_replaced = |start: &BytesStart| match start.local_name().as_ref() {
+        b"title" => title.is_some(),
+        b"creator" => !authors.is_empty(),
+        b"description" => summary.is_some(),
+        b"meta" => {
+            series.is_some()
+                && start
+                    .try_get_attribute("name")
+                    .ok()
+                    .flatten()
+                    .is_some_and(|name| name.value.starts_with(b"calibre:series"))
+        }
+        _ => false,
+    };
+    let removed_ids = removed_ids(opf, is_replaced)?;
+    let replaced = |start: &BytesStart| {
+        is_replaced(start) || refined_id(start).is_some_and(|id| removed_ids.contains(&id))
+    };
+
+    let mut reader = Reader::from_str(opf);
+    let mut writer = Writer::new(Vec::new());
+    let mut dc_prefix = None;
+    let mut in_metadata = false;
+
+    // Write the entry's metadata at the end of the package's metadata.
+    let write_metadata = |writer: &mut Writer<Vec<u8>>, dc_prefix: Option<&str>| {
+        let dc = |name: &str| match dc_prefix {
+            Some("") | None => name.to_string(),
+            Some(prefix) => format!("{}:{}", prefix, name),
+        };
+        let mut element = |name: String, text: &str| {
+            writer
+                .create_element(name.as_str())
+                .write_text_content(BytesText::new(text))
+                .map(|_| ())
+        };
+
+        if let Some(title) = title {
+            element(dc("title"), title)?;
+        }
+        for author in &authors {
+            element(dc("creator"), author)?;
+        }
+        if let Some(summary) = summary {
+            element(dc("description"), summary)?;
+        }
+        if let Some(series) = &series {
+            writer
+                .create_element("meta")
+                .with_attributes([("name", "calibre:series"), ("content", &series.name)])
+                .write_empty()?;
+            if let Some(position) = series.position {
+                writer
+                    .create_element("meta")
+                    .with_attributes([
+                        ("name", "calibre:series_index"),
+                        ("content", position.to_string().as_str()),
+                    ])
+                    .write_empty()?;
+            }
+        }
+        Ok::<_, quick_xml::Error>(())
+    };
+
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(start) if in_metadata && replaced(&start) => {
+                reader.read_to_end(start.name())?;
+            }
+            Event::Empty(start) if in_metadata && replaced(&start) => {}
+            Event::Start(mut start) if start.local_name().as_ref() == b"metadata" => {
+                dc_prefix = dc_prefix.or_else(|| namespace_prefix(&start, DC_NAMESPACE));
+                if dc_prefix.is_none() {
+                    start.push_attribute(("xmlns:dc", DC_NAMESPACE));
+                    dc_prefix = Some("dc".to_string());
+                }
+                in_metadata = true;
+                writer.write_event(Event::Start(start))?;
+            }
+            // An empty `<metadata/>` is opened up so the entry's metadata can go in it.
+            Event::Empty(mut start) if start.local_name().as_ref() == b"metadata" => {
+                dc_prefix = dc_prefix.or_else(|| namespace_prefix(&start, DC_NAMESPACE));
+                if dc_prefix.is_none() {
+                    start.push_attribute(("xmlns:dc", DC_NAMESPACE));
+                    dc_prefix = Some("dc".to_string());
+                }
+                let end = start.to_end().into_owned();
+                writer.write_event(Event::Start(start))?;
+                write_metadata(&mut writer, dc_prefix.as_deref())?;
+                writer.write_event(Event::End(end))?;
+            }
+            Event::End(end) if in_metadata && end.local_name().as_ref() == b"metadata" => {
+                write_metadata(&mut writer, dc_prefix.as_deref())?;
+                in_metadata = false;
+                writer.write_event(Event::End(BytesEnd::new(
+                    String::from_utf8_lossy(end.name().as_ref()).into_owned(),
+                )))?;
+            }
+            Event::Start(start) => {
+                dc_prefix = dc_prefix.or_else(|| namespace_prefix(&start, DC_NAMESPACE));
+                writer.write_event(Event::Start(start))?;
+            }
+            event => writer.write_event(event)?,
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+/// The ids of the elements in the metadata of `opf` that are dropped, either because
+/// `is_replaced` says so or because they refine an element that is, so the elements refining
+/// those can be dropped too.
+fn removed_ids(
+    opf: &str,
+    is_replaced: impl Fn(&BytesStart) -> bool,
+) -> Result<HashSet<String>, quick_xml::Error> {
+    // The id and refined id of every refining element in the metadata that isn't replaced.
+    let mut refinements = Vec::new();
+    let mut removed = HashSet::new();
+
+    let mut reader = Reader::from_str(opf);
+    let mut in_metadata = false;
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(start) if start.local_name().as_ref() == b"metadata" => {
+                in_metadata = true;
+            }
+            Event::End(end) if end.local_name().as_ref() == b"metadata" => in_metadata = false,
+            Event::Start(start) | Event::Empty(start) if in_metadata => {
+                let id = attribute(&start, "id");
+                if is_replaced(&start) {
+                    removed.extend(id);
+                } else if let Some(refines) = refined_id(&start) {
+                    refinements.push((id, refines));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Refinements can refine each other, so keep going until nothing else is removed.
+    loop {
+        let before = removed.len();
+        for (id, refines) in &refinements {
+            if removed.contains(refines) {
+                removed.extend(id.clone());
+            }
+        }
+        if removed.len() == before {
+            break;
+        }
+    }
+    Ok(removed)
+}
+
+/// The unescaped value of the attribute `name` of `start`.
+fn attribute(start: &BytesStart, name: &str) -> Option<String> {
+    let attribute = start.try_get_attribute(name).ok()??;
+    attribute
+        .unescape_value()
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+/// The id of the element `start` refines, from its `refines="#id"` attribute.
+fn refined_id(start: &BytesStart) -> Option<String> {
+    attribute(start, "refines")?
+        .strip_prefix('#')
+        .map(str::to_string)
+}
+
+/// The prefix `start` binds to `namespace`, which is empty when it's the default namespace.
+fn namespace_prefix(start: &BytesStart, namespace: &str) -> Option<String> {
+    start.attributes().flatten().find_map(|attribute| {
+        if attribute.value.as_ref() != namespace.as_bytes() {
+            return None;
+        }
+        let key = attribute.key.as_ref();
+        match key.strip_prefix(b"xmlns") {
+            Some(b"") => Some(String::new()),
+            Some(prefix) => prefix
+                .strip_prefix(b":")
+                .map(|prefix| String::from_utf8_lossy(prefix).into_owned()),
+            None => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::tests::test_dir;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+    <rootfiles>
+        <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+    </rootfiles>
+</container>"#;
+
+    const PACKAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id">
+    <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+        <dc:title>Untitled</dc:title>
+        <dc:creator opf:role="aut">Unknown</dc:creator>
+        <dc:identifier id="id">urn:uuid:56e99d4d-bef9-445e-8162-35aaef306006</dc:identifier>
+        <dc:language>en</dc:language>
+        <meta name="calibre:series" content="Old Series"/>
+    </metadata>
+    <manifest>
+        <item id="chapter" href="chapter.xhtml" media-type="application/xhtml+xml"/>
+    </manifest>
+    <spine>
+        <itemref idref="chapter"/>
+    </spine>
+</package>"#;
+
+    /// Build an EPUB with the given files, with the `mimetype` file first and uncompressed.
+    fn epub(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("mimetype", stored).unwrap();
+        writer.write_all(b"application/epub+zip").unwrap();
+        for (name, contents) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Test that the entry's metadata replaces the EPUB's, keeping everything else in it.
+    #[test]
+    fn embed_metadata_round_trip() {
+        let dir = test_dir("embed-metadata");
+        let path = dir.join("book.epub");
+        fs::write(
+            &path,
+            epub(&[
+                (CONTAINER_PATH, CONTAINER),
+                ("OEBPS/content.opf", PACKAGE),
+                ("OEBPS/chapter.xhtml", "<html>Chapter</html>"),
+            ]),
+        )
+        .unwrap();
+
+        let xml = include_str!("../tests/feed-series.xml");
+        let entry = crate::opds::read_feed(xml.as_bytes())
+            .unwrap()
+            .entries
+            .remove(1);
+        let description = Some("The sequel to Dune & more.");
+
+        let bytes = embed_metadata(&path, &entry, description).unwrap();
+        assert_eq!(bytes, fs::metadata(&path).unwrap().len());
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        drop(mimetype);
+        assert_eq!(
+            read_file(&mut archive, "OEBPS/chapter.xhtml").unwrap(),
+            "<html>Chapter</html>"
+        );
+
+        let opf = read_file(&mut archive, "OEBPS/content.opf").unwrap();
+        assert!(opf.contains("<dc:title>Dune Messiah</dc:title>"));
+        assert!(opf.contains("<dc:creator>Frank Herbert</dc:creator>"));
+        assert!(opf.contains("<dc:description>The sequel to Dune &amp; more.</dc:description>"));
+        assert!(opf.contains(r#"<meta name="calibre:series" content="Dune Chronicles"/>"#));
+        assert!(opf.contains(r#"<meta name="calibre:series_index" content="2"/>"#));
+        assert!(opf.contains("<dc:language>en</dc:language>"));
+        assert!(opf.contains("<itemref idref=\"chapter\"/>"));
+        assert!(!opf.contains("Untitled"));
+        assert!(!opf.contains("Unknown"));
+        assert!(!opf.contains("Old Series"));
+
+        // Embedding the same metadata again doesn't change the package document.
+        assert_eq!(rewrite_package(&opf, &entry, description).unwrap(), opf);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Test that the dublin core prefix is declared when the package document doesn't use one.
+    #[test]
+    fn embed_metadata_declares_namespace() {
+        let opf =
+            r#"<package xmlns="http://www.idpf.org/2007/opf"><metadata></metadata></package>"#;
+        let entry = Entry {
+            title: "Dune".to_string(),
+            ..Default::default()
+        };
+        let opf = rewrite_package(opf, &entry, None).unwrap();
+        assert_eq!(
+            opf,
+            r#"<package xmlns="http://www.idpf.org/2007/opf"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>Dune</dc:title></metadata></package>"#
+        );
+    }
+
+    /// Test that an empty metadata element gets the entry's metadata.
+    #[test]
+    fn embed_metadata_empty_metadata() {
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf"><metadata/><spine/></package>"#;
+        let entry = Entry {
+            title: "Dune".to_string(),
+            ..Default::default()
+        };
+        let opf = rewrite_package(opf, &entry, None).unwrap();
+        assert_eq!(
+            opf,
+            r#"<package xmlns="http://www.idpf.org/2007/opf"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>Dune</dc:title></metadata><spine/></package>"#
+        );
+    }
+
+    /// Test that EPUB 3 refinements of replaced elements are dropped along with them, and those
+    /// of the elements that are kept stay.
+    #[test]
+    fn embed_metadata_drops_refinements() {
+        let opf = r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title id="t">Untitled</dc:title><meta refines="#t" property="title-type">main</meta><dc:creator id="c">Unknown</dc:creator><meta refines="#c" property="role" id="r">aut</meta><meta refines="#r" property="scheme">marc:relators</meta><dc:language id="l">en</dc:language><meta refines="#l" property="alternate-script">en-US</meta></metadata></package>"##;
+        let entry = Entry {
+            title: "Dune".to_string(),
+            ..Default::default()
+        };
+        let opf = rewrite_package(opf, &entry, None).unwrap();
+        assert_eq!(
+            opf,
+            r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:creator id="c">Unknown</dc:creator><meta refines="#c" property="role" id="r">aut</meta><meta refines="#r" property="scheme">marc:relators</meta><dc:language id="l">en</dc:language><meta refines="#l" property="alternate-script">en-US</meta><dc:title>Dune</dc:title></metadata></package>"##
+        );
+    }
+
+    /// Test that EPUBs without the expected structure are left untouched.
+    #[test]
+    fn embed_metadata_unexpected_structure() {
+        let entry = Entry {
+            title: "Dune".to_string(),
+            ..Default::default()
+        };
+
+        let dir = test_dir("embed-metadata-unexpected");
+        let path = dir.join("book.epub");
+        for contents in [
+            epub(&[("OEBPS/content.opf", PACKAGE)]),
+            epub(&[(CONTAINER_PATH, "<container/>")]),
+            epub(&[(CONTAINER_PATH, CONTAINER)]),
+            b"<html>Not Found</html>".to_vec(),
+        ] {
+            fs::write(&path, &contents).unwrap();
+            assert!(embed_metadata(&path, &entry, None).is_err());
+            assert_eq!(fs::read(&path).unwrap(), contents);
+            assert!(!path.with_extension("epub.embedded").exists());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
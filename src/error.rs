@@ -0,0 +1,104 @@
+//! The errors a sync can fail with, so callers can tell failure modes apart.
+
+use std::{any::Any, error::Error as StdError, io};
+
+use thiserror::Error;
+
+/// An error that stopped a sync.
+#[derive(Debug, Error)]
+pub enum SyncError {
+    /// The settings can't be used as configured, such as a server URL that isn't a valid URL.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    /// A request to `url` failed, either because the server couldn't be reached or because it
+    /// responded with an error.
+    #[error("request to {url} failed")]
+    Network {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The content described by `what`, such as a feed or the state file, couldn't be parsed.
+    #[error("can't parse {what}")]
+    Parse {
+        what: String,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    /// Logging in to the server at `url` failed.
+    #[error("login to {url} failed: {reason}")]
+    Auth { url: String, reason: String },
+    /// The server at `url` responded successfully, but with only `bytes` bytes, too few to be the
+    /// document that was requested.
+    #[error("{url} responded with only {bytes} bytes")]
+    Incomplete { url: String, bytes: u64 },
+    /// A local file operation failed, described by `context`.
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The thread prefetching the pages after `url` panicked with `message`.
+    #[error("prefetching the pages after {url} failed: {message}")]
+    Prefetch { url: String, message: String },
+}
+
+impl SyncError {
+    /// Build a `Network` error for a request to `url`.
+    pub fn network(url: impl ToString, source: reqwest::Error) -> Self {
+        SyncError::Network {
+            url: url.to_string(),
+            source,
+        }
+    }
+
+    /// Build a `Parse` error for the content described by `what`.
+    pub fn parse(what: impl ToString, source: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        SyncError::Parse {
+            what: what.to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// Build an `Io` error for the file operation described by `context`.
+    pub fn io(context: impl ToString, source: io::Error) -> Self {
+        SyncError::Io {
+            context: context.to_string(),
+            source,
+        }
+    }
+
+    /// Build a `Prefetch` error from the `payload` the thread prefetching the pages after `url`
+    /// panicked with.
+    pub fn prefetch(url: impl ToString, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "the thread panicked".to_string(),
+            },
+        };
+        SyncError::Prefetch {
+            url: url.to_string(),
+            message,
+        }
+    }
+
+    /// Whether the error was caused by the disk being full, in which case every other file written
+    /// to it would fail the same way.
+    pub fn is_storage_full(&self) -> bool {
+        matches!(self, SyncError::Io { source, .. } if source.kind() == io::ErrorKind::StorageFull)
+    }
+
+    /// Whether the error was caused by a request exceeding the redirect limit.
+    pub fn is_redirect(&self) -> bool {
+        matches!(self, SyncError::Network { source, .. } if source.is_redirect())
+    }
+}
+
+impl From<url::ParseError> for SyncError {
+    fn from(err: url::ParseError) -> Self {
+        SyncError::Config(format!("invalid URL: {}", err))
+    }
+}
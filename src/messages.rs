@@ -0,0 +1,228 @@
+//! Templates that replace the built-in notification messages, i.e. to translate them.
+
+use std::fmt::Display;
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+/// Templates for the notifications shown while syncing. Unset templates use the built-in English
+/// messages.
+///
+/// Templates are plain text with placeholders in braces, i.e. `{count} livres de {server}`. Write
+/// `{{` and `}}` for literal braces.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Messages {
+    /// Shown when documents start downloading from a server, with the placeholders `{count}`,
+    /// `{server}` and `{breakdown}`, the number of documents of each file type.
+    pub downloading: Option<String>,
+    /// Shown when a server has finished syncing, with the placeholders `{server}`, `{size}`, the
+    /// total size downloaded, and `{seconds}`.
+    pub finished: Option<String>,
+    /// Shown when a document fails to download, with the placeholders `{title}` and `{error}`.
+    pub error: Option<String>,
+    /// Shown when nothing was downloaded, without any placeholders.
+    #[serde(alias = "up_to_date")]
+    pub up_to_date: Option<String>,
+}
+
+impl Messages {
+    /// Check that every template only uses the placeholders it's given.
+    pub fn validate(&self) -> Result<(), Error> {
+        let templates = [
+            (
+                "downloading",
+                &self.downloading,
+                &["count", "server", "breakdown"][..],
+            ),
+            (
+                "finished",
+                &self.finished,
+                &["server", "size", "seconds"][..],
+            ),
+            ("error", &self.error, &["title", "error"][..]),
+            ("up-to-date", &self.up_to_date, &[][..]),
+        ];
+
+        for (key, template, known) in templates {
+            let Some(template) = template else {
+                continue;
+            };
+            for placeholder in placeholders(template)
+                .map_err(|err| format_err!("invalid template messages.{}: {}", key, err))?
+            {
+                if !known.contains(&placeholder) {
+                    let known: Vec<_> = known.iter().map(|name| format!("{{{}}}", name)).collect();
+                    return Err(format_err!(
+                        "unknown placeholder {{{}}} in messages.{}, expected {}",
+                        placeholder,
+                        key,
+                        match known.is_empty() {
+                            true => "none".to_string(),
+                            false => known.join(", "),
+                        }
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The message shown when `count` documents start downloading from `server`.
+    pub fn downloading(&self, count: usize, server: &str, breakdown: &str) -> Option<String> {
+        let template = self.downloading.as_deref()?;
+        Some(render(
+            template,
+            &[
+                ("count", &count),
+                ("server", &server),
+                ("breakdown", &breakdown),
+            ],
+        ))
+    }
+
+    /// The message shown when `server` has finished syncing.
+    pub fn finished(&self, server: &str, size: &str, seconds: u64) -> Option<String> {
+        let template = self.finished.as_deref()?;
+        Some(render(
+            template,
+            &[("server", &server), ("size", &size), ("seconds", &seconds)],
+        ))
+    }
+
+    /// The message shown when the document `title` fails to download with `error`.
+    pub fn error(&self, title: &str, error: &dyn Display) -> Option<String> {
+        let template = self.error.as_deref()?;
+        Some(render(template, &[("title", &title), ("error", error)]))
+    }
+
+    /// The message shown when nothing was downloaded.
+    pub fn up_to_date(&self) -> Option<String> {
+        self.up_to_date
+            .as_deref()
+            .map(|template| render(template, &[]))
+    }
+}
+
+/// The parts a template is made of.
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split `template` into text and placeholders, erroring on unbalanced braces.
+fn parse(template: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        parts.push(Part::Text(&rest[..start]));
+        let brace = &rest[start..start + 1];
+        rest = &rest[start + 1..];
+
+        // Doubled braces are literal ones.
+        if let Some(after) = rest.strip_prefix(brace) {
+            parts.push(Part::Text(brace));
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            return Err("unmatched }".to_string());
+        }
+
+        let end = rest.find('}').ok_or("unclosed {")?;
+        parts.push(Part::Placeholder(rest[..end].trim()));
+        rest = &rest[end + 1..];
+    }
+    parts.push(Part::Text(rest));
+    Ok(parts)
+}
+
+/// The names of the placeholders used in `template`.
+fn placeholders(template: &str) -> Result<Vec<&str>, String> {
+    Ok(parse(template)?
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Placeholder(name) => Some(name),
+            Part::Text(_) => None,
+        })
+        .collect())
+}
+
+/// Fill in the placeholders of `template` with `values`. Templates are validated when the
+/// settings are loaded, so any placeholder without a value is left as it is.
+fn render(template: &str, values: &[(&str, &dyn Display)]) -> String {
+    let Ok(parts) = parse(template) else {
+        return template.to_string();
+    };
+
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Text(text) => text.to_string(),
+            Part::Placeholder(name) => values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map_or_else(|| format!("{{{}}}", name), |(_, value)| value.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that templates are rendered with their placeholders filled in, falling back to the
+    /// built-in messages when unset.
+    #[test]
+    fn render_messages() {
+        let messages = Messages {
+            downloading: Some(
+                "Téléchargement de {count} livres de « {server} » ({breakdown})".into(),
+            ),
+            error: Some("{{{title}}} : {error}".into()),
+            up_to_date: Some("Tout est à jour".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            messages.downloading(3, "calibre", "2 EPUB, 1 PDF").unwrap(),
+            "Téléchargement de 3 livres de « calibre » (2 EPUB, 1 PDF)"
+        );
+        assert_eq!(
+            messages.error("Dune", &"timed out").unwrap(),
+            "{Dune} : timed out"
+        );
+        assert_eq!(messages.up_to_date().unwrap(), "Tout est à jour");
+        assert!(messages.finished("calibre", "1.2 MB", 3).is_none());
+    }
+
+    /// Test that unknown placeholders and unbalanced braces are rejected.
+    #[test]
+    fn validate_messages() {
+        let messages = |finished: &str| Messages {
+            finished: Some(finished.to_string()),
+            ..Default::default()
+        };
+        assert!(Messages::default().validate().is_ok());
+        assert!(messages("Synced {server}: {size} in {seconds}s {{ok}}")
+            .validate()
+            .is_ok());
+
+        let err = messages("Synced {title}").validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown placeholder {title} in messages.finished, expected {server}, {size}, {seconds}"
+        );
+        assert!(messages("Synced {server").validate().is_err());
+        assert!(messages("Synced server}").validate().is_err());
+
+        let up_to_date = Messages {
+            up_to_date: Some("Nothing new on {server}".into()),
+            ..Default::default()
+        };
+        assert!(up_to_date
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .ends_with("expected none"));
+    }
+}
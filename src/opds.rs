@@ -1,12 +1,32 @@
 //! Contains the structures for parsing OPDS feeds.
 
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    io::{BufRead, BufReader, Read},
+    mem,
+    path::PathBuf,
+};
 
-use crate::LinkType;
+use anyhow::{format_err, Error};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader, Writer,
+};
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use url::{ParseError, Url};
+
+use crate::{
+    base_mime_type, mime_type_for_extension,
+    settings::{DescriptionSource, IdentifierSource},
+    LinkType,
+};
 
 /// Holds the settings for a single instance of a server.
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Instance {
     /// A URL string pointing to an OPDS feed.
@@ -15,39 +35,447 @@ pub struct Instance {
     pub username: Option<String>,
     /// Optional password for basic authentication to the server.
     pub password: Option<String>,
+    /// Optional cookie string sent verbatim as the `Cookie` header of every request to this
+    /// server, i.e. a `cf_clearance` cookie copied from a browser to get past a Cloudflare
+    /// challenge. Replaces any session cookie from `login`.
+    pub cookie: Option<String>,
+    /// Optional name of the directory documents from this server are saved in when
+    /// `use-server-name-directories` is enabled, instead of the server's name.
+    pub directory: Option<String>,
+    /// Optional form login used by servers that authenticate with a session cookie rather than
+    /// basic authentication.
+    pub login: Option<Login>,
+    /// Whether documents synced from this server should be marked as finished, i.e. for servers
+    /// holding an archive of books that have already been read.
+    pub finished: bool,
+    /// Whether documents that were already synced as finished from this server should never be
+    /// downloaded or added again, even when forced or if their entry has been updated.
+    pub skip_finished: bool,
+    /// The page documents synced from this server should start on.
+    pub current_page: usize,
+    /// Whether documents synced from this server should be marked as opened at the time of the
+    /// sync.
+    pub set_opened: bool,
+    /// The maximum number of documents downloaded from this server in a single sync, overriding
+    /// the global `max-downloads-per-sync`.
+    pub max_downloads: Option<usize>,
+    /// Optional directory documents from this server are saved in instead of the save path the
+    /// fetcher was run with. It has to be inside the library.
+    pub save_path: Option<PathBuf>,
+    /// Whether the sync should fail when an entry in this server's feed is missing its title or
+    /// id, rather than carrying on with an empty one. Meant for testing a feed while writing it.
+    pub strict: bool,
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            username: None,
+            password: None,
+            cookie: None,
+            directory: None,
+            login: None,
+            finished: false,
+            skip_finished: false,
+            current_page: 0,
+            set_opened: true,
+            max_downloads: None,
+            strict: false,
+            save_path: None,
+        }
+    }
+}
+
+impl Instance {
+    /// The URL links in the server's feeds are resolved against. The instance URL is treated as a
+    /// directory whether or not it ends with a slash, so `https://host/opds` and
+    /// `https://host/opds/` resolve every link the same way. `file://` URLs point to a saved feed
+    /// rather than a directory, so links resolve against the directory it's in.
+    pub fn base_url(&self) -> Result<Url, ParseError> {
+        let mut url = Url::parse(&self.url)?;
+        if url.scheme() != "file" && !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        Ok(url)
+    }
+
+    /// Resolve `href`, which may be absolute or relative, against the server's base URL.
+    pub fn resolve(&self, href: &str) -> Result<Url, ParseError> {
+        self.base_url()?.join(href)
+    }
+}
+
+/// Settings for a form login that is POSTed before any other request to obtain a session cookie.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Login {
+    /// The URL the login form is POSTed to. Relative URLs are resolved against the instance URL.
+    pub url: String,
+    /// The name of the form field holding the username.
+    pub username_field: String,
+    /// The name of the form field holding the password.
+    pub password_field: String,
+}
+
+impl Default for Login {
+    fn default() -> Self {
+        Self {
+            url: "/login".to_string(),
+            username_field: "username".to_string(),
+            password_field: "password".to_string(),
+        }
+    }
 }
 
 /// The structure of an OPDS feed.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Feed {
-    /// List of book entries in the feed.
-    #[serde(rename = "entry")]
+    /// List of book entries in the feed, including those nested in groups.
+    #[serde(rename = "entry", default)]
     pub entries: Vec<Entry>,
     /// List of links in the feed.
-    #[serde(rename = "link")]
+    #[serde(rename = "link", default)]
     pub links: Vec<Link>,
+    /// When the feed as a whole was last updated.
+    #[serde(rename = "updated", default, deserialize_with = "deserialize_date")]
+    pub feed_updated: Option<DateTime<Utc>>,
+    /// The total number of entries across every page of the feed, if advertised through
+    /// OpenSearch.
+    #[serde(rename = "totalResults")]
+    pub total_results: Option<usize>,
+    /// The number of entries on each page of the feed, if advertised through OpenSearch.
+    #[serde(rename = "itemsPerPage")]
+    pub items_per_page: Option<usize>,
+}
+
+impl Feed {
+    /// Check that every entry has a title and an id, returning an error naming the first entry
+    /// that doesn't. Elements that are present but empty count as missing.
+    pub fn check_required(&self) -> Result<(), Error> {
+        for (i, entry) in self.entries.iter().enumerate() {
+            let name = match (entry.title.trim(), entry.id.trim()) {
+                ("", "") => format!("entry {}", i + 1),
+                ("", id) => format!("entry {} ({})", i + 1, id),
+                (title, _) => format!("entry {} ('{}')", i + 1, title),
+            };
+            if entry.title.trim().is_empty() {
+                return Err(format_err!("{} has no title", name));
+            }
+            if entry.id.trim().is_empty() {
+                return Err(format_err!("{} has no id", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The approximate number of pages in the feed, if the feed advertises its size.
+    pub fn page_count(&self) -> Option<usize> {
+        match (self.total_results, self.items_per_page) {
+            (Some(total), Some(per_page)) if per_page > 0 => Some(total.div_ceil(per_page)),
+            _ => None,
+        }
+    }
 }
 
 /// The structure of an OPDS feed entry. Usually represents a book.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
-    /// The title of the book.
+    /// The title of the book. Empty if the entry doesn't have one.
+    #[serde(default)]
     pub title: String,
-    /// The unique identifier of the book.
+    /// The unique identifier of the book. Empty if the entry doesn't have one.
+    #[serde(default)]
     pub id: String,
+    /// Other identifiers of the book given by `<dc:identifier>` elements, such as its ISBN.
+    #[serde(rename = "identifier")]
+    pub identifiers: Option<Vec<Identifier>>,
     /// The authors of the book.
     #[serde(rename = "author")]
     pub authors: Option<Vec<Author>>,
     /// The publisher of the book.
     #[serde(rename = "publisher")]
     pub publishers: Option<Vec<Publisher>>,
-    /// The date the book was published.
+    /// The date the entry was published to the catalog, which some servers use for the date the
+    /// book itself was published.
     pub published: Option<DateTime<Utc>>,
+    /// The date the book was originally published.
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub issued: Option<DateTime<Utc>>,
+    /// The date the entry was last updated in the catalog.
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub updated: Option<DateTime<Utc>>,
+    /// The rights or license the book is distributed under. Matches both `<rights>` and
+    /// `<dc:rights>`, as elements are matched by their local name. Only the first is kept when an
+    /// entry has both, see [read_entry].
+    pub rights: Option<String>,
+    /// The summaries of the book. Atom only allows one, but some servers give several of
+    /// different types.
+    #[serde(rename = "summary")]
+    pub summaries: Option<Vec<Text>>,
+    /// The content of the entry, which servers use for a longer description of the book.
+    #[serde(rename = "content")]
+    pub contents: Option<Vec<Text>>,
+    /// The collections the book belongs to, such as its series.
+    #[serde(rename = "belongsTo")]
+    pub belongs_to: Option<BelongsTo>,
+    /// Nonstandard metadata given by `<meta name="..." content="..."/>` elements, which Calibre
+    /// uses for the series.
+    #[serde(rename = "meta")]
+    pub meta: Option<Vec<Meta>>,
     /// The links to the book's resources. Usually contains a link to the book files.
     #[serde(rename = "link")]
     pub links: Option<Vec<Link>>,
 }
 
+impl Entry {
+    /// Whether the entry links to a cover or thumbnail image.
+    pub fn has_cover(&self) -> bool {
+        self.links
+            .iter()
+            .flatten()
+            .any(|link| matches!(link.rel, Some(LinkType::Cover) | Some(LinkType::Thumbnail)))
+    }
+
+    /// The acquisition link to download the book from, chosen by `preferred_file_types` which
+    /// lists MIME types from most to least preferred. A link of an earlier type is always chosen
+    /// over a link of a later one, wherever they appear in the entry. When the entry has several
+    /// links of the same type, the first of them is chosen.
+    ///
+    /// Types are compared without their parameters, so a link of type
+    /// `application/epub+zip; charset=utf-8` matches `application/epub+zip`. The returned link's
+    /// type is the base type it matched.
+    ///
+    /// If none of the links with a type match, links without a type are considered using the type
+    /// inferred from their href, which is then filled in on the returned link.
+    ///
+    /// An empty `preferred_file_types` accepts any file type, choosing the first link whose type
+    /// is known or can be inferred.
+    pub fn acquisition_link(&self, preferred_file_types: &[String]) -> Option<Link> {
+        self.link_of_rel(LinkType::Acquisition, preferred_file_types)
+    }
+
+    /// The open-access link to download a free copy of the book from, chosen by
+    /// `preferred_file_types` the same way as `acquisition_link`.
+    pub fn open_access_link(&self, preferred_file_types: &[String]) -> Option<Link> {
+        self.link_of_rel(LinkType::OpenAccess, preferred_file_types)
+    }
+
+    /// The link with the relation `rel` of the most preferred file type.
+    fn link_of_rel(&self, rel: LinkType, preferred_file_types: &[String]) -> Option<Link> {
+        let acquisition_links = || {
+            self.links
+                .iter()
+                .flatten()
+                .filter(|link| link.rel.as_ref() == Some(&rel))
+        };
+
+        if preferred_file_types.is_empty() {
+            return acquisition_links().find_map(|link| {
+                Some(Link {
+                    file_type: Some(link.resolved_file_type()?),
+                    ..link.clone()
+                })
+            });
+        }
+
+        let find = |typed: bool| {
+            preferred_file_types.iter().find_map(|file_type| {
+                let file_type = base_mime_type(file_type);
+                acquisition_links()
+                    .filter(|link| link.file_type.is_some() == typed)
+                    .find(|link| link.resolved_file_type().as_ref() == Some(&file_type))
+                    .map(|link| Link {
+                        file_type: Some(file_type),
+                        ..link.clone()
+                    })
+            })
+        };
+
+        find(true).or_else(|| find(false))
+    }
+
+    /// The alternate link to the complete OPDS catalog entry, for partial entries that leave out
+    /// some of the book's metadata. Alternate links of any other type, such as a web page or a
+    /// navigation feed, are never chosen.
+    pub fn full_entry_link(&self) -> Option<&Link> {
+        self.links
+            .iter()
+            .flatten()
+            .filter(|link| link.rel == Some(LinkType::Alternate))
+            .find(|link| link.is_full_entry())
+    }
+
+    /// Whether the book has been read on the server, if the feed says. This is taken from the
+    /// page streaming link servers like Komga add, the book being read once its last read page
+    /// is its last page.
+    pub fn is_read(&self) -> Option<bool> {
+        self.links
+            .iter()
+            .flatten()
+            .filter(|link| link.rel == Some(LinkType::PageStream))
+            .find_map(|link| {
+                let page_count: u32 = link.page_count.as_deref()?.trim().parse().ok()?;
+                let last_read_page: u32 = link.last_read_page.as_deref()?.trim().parse().ok()?;
+                Some(page_count > 0 && last_read_page >= page_count)
+            })
+    }
+
+    /// The identifier of the book chosen by `source`, falling back to the entry's id.
+    pub fn identifier(&self, source: IdentifierSource) -> String {
+        match source {
+            IdentifierSource::Id => None,
+            IdentifierSource::Uuid => self.id.strip_prefix("urn:uuid:").map(str::to_string),
+            IdentifierSource::Isbn => self.isbn(),
+        }
+        .unwrap_or_else(|| self.id.clone())
+    }
+
+    /// The first ISBN among the book's identifiers, without hyphens or spaces. Identifiers are
+    /// taken as ISBNs when their scheme is `ISBN` or they're prefixed with `urn:isbn:` or
+    /// `isbn:`, and only kept if they have the 10 or 13 digits of one.
+    pub fn isbn(&self) -> Option<String> {
+        self.identifiers.iter().flatten().find_map(|identifier| {
+            let value = identifier.value.trim();
+            let isbn = match &identifier.scheme {
+                Some(scheme) if scheme.eq_ignore_ascii_case("isbn") => value,
+                _ => ["urn:isbn:", "isbn:"].iter().find_map(|prefix| {
+                    value
+                        .get(..prefix.len())
+                        .filter(|start| start.eq_ignore_ascii_case(prefix))
+                        .map(|_| &value[prefix.len()..])
+                })?,
+            };
+
+            let isbn: String = isbn
+                .chars()
+                .filter(|c| *c != '-' && !c.is_whitespace())
+                .map(|c| c.to_ascii_uppercase())
+                .collect();
+            // ISBN-10s end with a check digit that can be an X.
+            let is_isbn = match isbn.len() {
+                10 => {
+                    isbn[..9].chars().all(|c| c.is_ascii_digit())
+                        && isbn[9..].chars().all(|c| c.is_ascii_digit() || c == 'X')
+                }
+                13 => isbn.chars().all(|c| c.is_ascii_digit()),
+                _ => false,
+            };
+            is_isbn.then_some(isbn)
+        })
+    }
+
+    /// The price of buying or subscribing to the book, if the entry has a buy or subscribe link
+    /// with one. Links can give a price in several currencies, of which the first is used.
+    pub fn price(&self) -> Option<&Price> {
+        self.links
+            .iter()
+            .flatten()
+            .filter(|link| matches!(link.rel, Some(LinkType::Buy) | Some(LinkType::Subscribe)))
+            .find_map(|link| link.prices.iter().flatten().next())
+    }
+
+    /// The series the book belongs to, if any. The standard `<opds:belongsTo>` element is
+    /// preferred, falling back to Calibre's `calibre:series` and `calibre:series_index` metadata.
+    pub fn series(&self) -> Option<Series> {
+        if let Some(series) = self.belongs_to.as_ref().and_then(|b| b.series.as_ref()) {
+            return Some(series.clone());
+        }
+
+        let name = self.meta_content("calibre:series")?;
+        Some(Series {
+            name: name.to_string(),
+            position: self
+                .meta_content("calibre:series_index")
+                .and_then(|index| index.parse().ok()),
+        })
+    }
+
+    /// The content of the first non-empty `<meta>` element with the given `name`.
+    fn meta_content(&self, name: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .flatten()
+            .filter(|meta| meta.name == name)
+            .filter_map(|meta| meta.content.as_deref())
+            .map(str::trim)
+            .find(|content| !content.is_empty())
+    }
+
+    /// The description of the book, taken from its summary or content as chosen by `source`.
+    /// HTML and XHTML descriptions are converted to plain text if `html_to_text` is set.
+    pub fn description(&self, source: DescriptionSource, html_to_text: bool) -> Option<String> {
+        let summaries = self.summaries.iter().flatten();
+        let contents = self.contents.iter().flatten();
+        let mut candidates: Vec<&Text> = match source {
+            DescriptionSource::Content => contents.chain(summaries).collect(),
+            DescriptionSource::Summary | DescriptionSource::PreferText => {
+                summaries.chain(contents).collect()
+            }
+        };
+        candidates.retain(|text| !text.value.trim().is_empty());
+        if source == DescriptionSource::PreferText {
+            // The sort is stable, so plain text candidates keep their order.
+            candidates.sort_by_key(|text| text.kind != TextKind::Text);
+        }
+
+        let text = candidates.first()?;
+        Some(match text.kind {
+            TextKind::Html | TextKind::Xhtml if html_to_text => html_to_plain_text(&text.value),
+            _ => text.value.trim().to_string(),
+        })
+    }
+
+    /// The year the book was published, preferring the original publication date over the date
+    /// it was published to the catalog.
+    pub fn year(&self) -> Option<i32> {
+        self.issued.or(self.published).map(|date| date.year())
+    }
+}
+
+/// The collections an OPDS feed entry belongs to, given by an `<opds:belongsTo>` element.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BelongsTo {
+    /// The series the book is part of.
+    pub series: Option<Series>,
+}
+
+/// A series of books, given by an `<opds:series>` element.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Series {
+    /// The name of the series.
+    #[serde(rename = "@name")]
+    pub name: String,
+    /// The position of the book in the series, which can be fractional for novellas and the
+    /// like.
+    #[serde(rename = "@position")]
+    pub position: Option<f32>,
+}
+
+/// An identifier of a book, given by a `<dc:identifier>` element.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Identifier {
+    /// The scheme of the identifier, such as `ISBN`, given by an `opf:scheme` attribute.
+    #[serde(rename = "@scheme")]
+    pub scheme: Option<String>,
+    /// The identifier itself.
+    #[serde(rename = "$text", default)]
+    pub value: String,
+}
+
+/// A nonstandard piece of metadata, given by a `<meta>` element.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Meta {
+    /// The name of the metadata, such as `calibre:series`.
+    #[serde(rename = "@name", default)]
+    pub name: String,
+    /// The value of the metadata.
+    #[serde(rename = "@content")]
+    pub content: Option<String>,
+}
+
 /// The author listed in an OPDS feed entry.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Author {
@@ -71,17 +499,605 @@ pub struct Link {
     pub href: Option<String>,
     #[serde(rename = "@type")]
     pub file_type: Option<String>,
+    /// A human readable label for the resource, such as the format or edition an acquisition
+    /// link downloads.
+    #[serde(rename = "@title")]
+    pub title: Option<String>,
+    /// The prices of buying or subscribing to the resource, one for each currency it's sold in.
+    #[serde(rename = "price")]
+    pub prices: Option<Vec<Price>>,
+    /// Whether the resource can currently be borrowed, as given by library feeds.
+    pub availability: Option<Availability>,
+    /// The number of pages of a page streaming link, from its `pse:count` attribute.
+    #[serde(rename = "@count")]
+    pub page_count: Option<String>,
+    /// The last page read of a page streaming link, from its `pse:lastRead` attribute.
+    #[serde(rename = "@lastRead")]
+    pub last_read_page: Option<String>,
+}
+
+impl Link {
+    /// The base MIME type of the linked resource, without any parameters. Links without a type
+    /// have it inferred from the
+    /// extension at the end of their href, such as `.epub`, or a final path segment naming the
+    /// file type, such as `/download/79/epub/`.
+    pub fn resolved_file_type(&self) -> Option<String> {
+        if let Some(file_type) = &self.file_type {
+            return Some(base_mime_type(file_type));
+        }
+
+        let href = self.href.as_deref()?;
+        let path = href.split(['?', '#']).next()?;
+        let segment = path.trim_end_matches('/').rsplit('/').next()?;
+        let extension = segment
+            .rsplit_once('.')
+            .map_or(segment, |(_, extension)| extension);
+        mime_type_for_extension(extension).map(str::to_string)
+    }
+
+    /// Whether the link's type is that of a complete OPDS catalog entry,
+    /// `application/atom+xml;type=entry;profile=opds-catalog`. The parameters may be in any
+    /// order, quoted, or differ in case.
+    pub fn is_full_entry(&self) -> bool {
+        let Some(file_type) = self.file_type.as_deref() else {
+            return false;
+        };
+        if base_mime_type(file_type) != "application/atom+xml" {
+            return false;
+        }
+
+        let param = |name: &str| {
+            file_type.split(';').skip(1).find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case(name)
+                    .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+            })
+        };
+        param("type").as_deref() == Some("entry")
+            && param("profile").as_deref() == Some("opds-catalog")
+    }
+
+    /// Whether the linked resource can be acquired right now. Links without an
+    /// `<opds:availability>` element are assumed to be available.
+    pub fn is_available(&self) -> bool {
+        self.availability
+            .as_ref()
+            .is_none_or(|availability| availability.status.is_available())
+    }
+}
+
+/// The availability of a library's copies of a resource, given by an `<opds:availability>`
+/// element.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Availability {
+    #[serde(rename = "@status")]
+    pub status: AvailabilityStatus,
+}
+
+/// The status of an `<opds:availability>` element.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvailabilityStatus {
+    /// A copy can be borrowed straight away.
+    #[default]
+    Available,
+    /// Every copy is on loan.
+    Unavailable,
+    /// Every copy is on loan and the user has placed a hold on one.
+    Reserved,
+    /// A held copy is ready to be borrowed.
+    Ready,
+    /// Any status the OPDS specification doesn't define.
+    #[serde(other)]
+    Unknown,
+}
+
+impl AvailabilityStatus {
+    /// Whether a resource with this status can be acquired. Unknown statuses are given the
+    /// benefit of the doubt.
+    pub fn is_available(self) -> bool {
+        !matches!(
+            self,
+            AvailabilityStatus::Unavailable | AvailabilityStatus::Reserved
+        )
+    }
+}
+
+/// The price of an OPDS buy or subscribe link, given by an `<opds:price>` element.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Price {
+    /// The ISO 4217 code of the currency the price is in.
+    #[serde(rename = "@currencycode")]
+    pub currency_code: Option<String>,
+    /// The amount, as given by the feed.
+    #[serde(rename = "$text")]
+    pub amount: String,
+}
+
+impl Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.currency_code {
+            Some(currency_code) => write!(f, "{} {}", self.amount.trim(), currency_code),
+            None => write!(f, "{}", self.amount.trim()),
+        }
+    }
+}
+
+/// Parse an OPDS feed from `reader` without buffering the whole document, which keeps memory use
+/// low for huge feeds on constrained devices. Every entry is parsed as soon as it's been read and
+/// passed to `on_entry`, and the rest of the feed is returned without any entries. Entries nested
+/// in groups are passed to `on_entry` too.
+pub fn stream_feed<R: BufRead>(reader: R, mut on_entry: impl FnMut(Entry)) -> Result<Feed, Error> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+
+    // Everything but the entries is written to a skeleton of the feed, which is small enough to
+    // be deserialized in one go once the whole feed has been read.
+    let mut skeleton = Writer::new(Vec::new());
+    let mut depth = 0;
+    let mut in_group = false;
+
+    loop {
+        let is_entry_depth = |depth: usize| depth == 1 || (depth == 2 && in_group);
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(start)
+                if is_entry_depth(depth) && start.local_name().as_ref() == b"entry" =>
+            {
+                let start = start.into_owned();
+                on_entry(read_entry(&mut reader, start)?);
+            }
+            Event::Empty(start)
+                if is_entry_depth(depth) && start.local_name().as_ref() == b"entry" =>
+            {
+                let mut writer = Writer::new(Vec::new());
+                writer.write_event(Event::Empty(start))?;
+                on_entry(quick_xml::de::from_reader(writer.into_inner().as_slice())?);
+            }
+            Event::Start(start) => {
+                in_group |= depth == 1 && start.local_name().as_ref() == b"group";
+                depth += 1;
+                skeleton.write_event(Event::Start(start))?;
+            }
+            Event::End(end) => {
+                depth -= 1;
+                in_group &= depth > 1;
+                skeleton.write_event(Event::End(end))?;
+            }
+            Event::Eof => break,
+            event if depth > 0 => skeleton.write_event(event)?,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let skeleton = skeleton.into_inner();
+    if skeleton.is_empty() {
+        return Err(format_err!("feed has no root element"));
+    }
+    quick_xml::de::from_reader(skeleton.as_slice()).map_err(Into::into)
+}
+
+/// Read the rest of an entry that begins with `start` from `reader` and deserialize it.
+///
+/// Entries can have both `<rights>` and `<dc:rights>`, which deserialize into the same field, so
+/// every rights element after the first is dropped rather than failing on the duplicate field.
+fn read_entry<R: BufRead>(reader: &mut Reader<R>, start: BytesStart) -> Result<Entry, Error> {
+    let mut writer = Writer::new(Vec::new());
+    writer.write_event(Event::Start(start))?;
+
+    let mut buf = Vec::new();
+    let mut depth = 1;
+    let mut has_rights = false;
+    while depth > 0 {
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(start) | Event::Empty(start)
+                if depth == 1
+                    && start.local_name().as_ref() == b"rights"
+                    && mem::replace(&mut has_rights, true) =>
+            {
+                if let Event::Start(start) = &event {
+                    let end = start.to_end().into_owned();
+                    reader.read_to_end_into(end.name(), &mut Vec::new())?;
+                }
+                buf.clear();
+                continue;
+            }
+            _ => {}
+        }
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => return Err(format_err!("feed ended in the middle of an entry")),
+            _ => {}
+        }
+        writer.write_event(event)?;
+        buf.clear();
+    }
+
+    quick_xml::de::from_reader(writer.into_inner().as_slice()).map_err(Into::into)
+}
+
+/// Parse a single OPDS entry from `xml`, such as a complete entry fetched on its own.
+pub fn parse_entry(xml: &str) -> Result<Entry, Error> {
+    let mut reader = Reader::from_reader(xml.as_bytes());
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(start) => {
+                let start = start.into_owned();
+                return read_entry(&mut reader, start);
+            }
+            Event::Empty(_) => return quick_xml::de::from_str(xml).map_err(Into::into),
+            Event::Eof => return Err(format_err!("entry has no root element")),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Parse a whole OPDS feed from `reader` with [stream_feed], collecting its entries. The raw XML
+/// is never held in memory at once, but every parsed entry is, as a sync filters and downloads
+/// the entries of a feed once all of its pages have been crawled.
+pub fn read_feed<R: Read>(reader: R) -> Result<Feed, Error> {
+    let mut entries = Vec::new();
+    let mut feed = stream_feed(BufReader::new(reader), |entry| entries.push(entry))?;
+    feed.entries = entries;
+    Ok(feed)
+}
+
+/// Parse a date leniently, accepting RFC 3339 timestamps as well as timestamps without a timezone,
+/// plain dates and years, which are assumed to be UTC.
+pub fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    DateTime::parse_from_rfc3339(s)
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+                .ok()
+                .map(|date| date.and_utc())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| date.and_utc())
+        })
+        .or_else(|| {
+            s.parse::<i32>()
+                .ok()
+                .filter(|_| s.len() == 4)
+                .and_then(|year| NaiveDate::from_ymd_opt(year, 1, 1))
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| date.and_utc())
+        })
+}
+
+/// An Atom text construct, such as a `<summary>` or `<content>`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Text {
+    /// The type of the text, which says how to read its value.
+    #[serde(rename = "@type")]
+    pub kind: TextKind,
+    /// The text, escaped HTML, or XHTML markup without the `<div>` Atom wraps it in.
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+/// The type of an Atom text construct, given by its `type` attribute.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextKind {
+    /// Plain text.
+    #[default]
+    Text,
+    /// Escaped HTML.
+    Html,
+    /// Inline XHTML elements.
+    Xhtml,
+}
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let node = Node::deserialize(deserializer)?;
+        let kind = match node.attribute("type") {
+            Some("html") | Some("text/html") => TextKind::Html,
+            Some("xhtml") | Some("application/xhtml+xml") => TextKind::Xhtml,
+            _ => TextKind::Text,
+        };
+
+        let value = match kind {
+            // Atom wraps XHTML in a `<div>` that isn't part of the text.
+            TextKind::Xhtml => match node.children.as_slice() {
+                [Child::Element(name, div)] if name == "div" => div.markup(),
+                // Serialized entries, such as cached ones, hold the markup as text.
+                [Child::Text(markup)] => markup.clone(),
+                _ => node.markup(),
+            },
+            TextKind::Text | TextKind::Html => node.text(),
+        };
+        Ok(Text { kind, value })
+    }
+}
+
+/// An XML element deserialized without knowing its structure, used to read inline XHTML.
+#[derive(Default, Debug)]
+struct Node {
+    attributes: Vec<(String, String)>,
+    children: Vec<Child>,
+}
+
+/// A child of a [Node], in document order.
+#[derive(Debug)]
+enum Child {
+    Text(String),
+    Element(String, Node),
+}
+
+impl Node {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The text directly in the node.
+    fn text(&self) -> String {
+        let text: Vec<&str> = self
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Child::Text(text) => Some(text.as_str()),
+                Child::Element(..) => None,
+            })
+            .collect();
+        text.join(" ")
+    }
+
+    /// The node's children as XHTML markup. Text is trimmed when it's parsed, so children are
+    /// separated by spaces to keep words apart.
+    fn markup(&self) -> String {
+        let children: Vec<String> = self
+            .children
+            .iter()
+            .map(|child| match child {
+                Child::Text(text) => quick_xml::escape::escape(text).into_owned(),
+                Child::Element(name, node) => {
+                    let mut markup = format!("<{}", name);
+                    for (key, value) in &node.attributes {
+                        if key != "xmlns" {
+                            let value = quick_xml::escape::escape(value);
+                            markup.push_str(&format!(" {}=\"{}\"", key, value));
+                        }
+                    }
+                    match node.children.is_empty() {
+                        true => markup.push_str("/>"),
+                        false => markup.push_str(&format!(">{}</{}>", node.markup(), name)),
+                    }
+                    markup
+                }
+            })
+            .collect();
+        children.join(" ")
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = Node;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an XML element")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Node, E> {
+                Ok(Node {
+                    children: vec![Child::Text(text.to_string())],
+                    ..Default::default()
+                })
+            }
+
+            fn visit_unit<E: serde::de::Error>(self) -> Result<Node, E> {
+                Ok(Node::default())
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+                let mut node = Node::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    if let Some(attribute) = key.strip_prefix('@') {
+                        node.attributes
+                            .push((attribute.to_string(), map.next_value()?));
+                    } else if key == "$text" || key == "$value" {
+                        node.children.push(Child::Text(map.next_value()?));
+                    } else {
+                        node.children.push(Child::Element(key, map.next_value()?));
+                    }
+                }
+                Ok(node)
+            }
+        }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+/// Convert HTML to plain text, putting block elements on their own lines and decoding entities.
+pub fn html_to_plain_text(html: &str) -> String {
+    const BLOCK_ELEMENTS: [&str; 14] = [
+        "p",
+        "div",
+        "br",
+        "li",
+        "ul",
+        "ol",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "blockquote",
+        "tr",
+    ];
+
+    // Line breaks in HTML are only whitespace, so lines are broken by block elements alone.
+    let html = html.replace(['\r', '\n'], " ");
+    let mut text = String::new();
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+
+        let tag = &rest[start + 1..start + end];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        text.push(match BLOCK_ELEMENTS.contains(&name.as_str()) {
+            true => '\n',
+            false => ' ',
+        });
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+
+    let text = decode_entities(&text);
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let mut line = words.join(" ");
+            // Tags between words and punctuation leave a space before the punctuation.
+            for punctuation in [".", ",", ";", ":", "!", "?", ")"] {
+                line = line.replace(&format!(" {}", punctuation), punctuation);
+            }
+            line
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+/// Decode the named entities common in HTML descriptions, along with numeric ones.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => match entity.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                    .ok()
+                    .and_then(char::from_u32),
+                Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                None => None,
+            },
+        });
+
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Deserialize an optional date with [parse_date], treating dates that can't be parsed as absent
+/// rather than failing the whole feed.
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = Option::<String>::deserialize(deserializer)?;
+    Ok(s.as_deref().and_then(parse_date))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Test that links resolve the same way whether or not the instance URL ends with a slash.
+    #[test]
+    fn instance_resolves_links() {
+        let instance = |url: &str| Instance {
+            url: url.to_string(),
+            ..Default::default()
+        };
+        let with_slash = instance("https://books.example.net:8443/opds/");
+        let without_slash = instance("https://books.example.net:8443/opds");
+
+        let saved = instance("file:///mnt/onboard/feeds/calibre.xml");
+        assert_eq!(
+            saved.resolve("books/dune.epub").unwrap().as_str(),
+            "file:///mnt/onboard/feeds/books/dune.epub"
+        );
+
+        for (href, resolved) in [
+            (
+                "/opds/download/79/epub/",
+                "https://books.example.net:8443/opds/download/79/epub/",
+            ),
+            (
+                "download/79/epub/",
+                "https://books.example.net:8443/opds/download/79/epub/",
+            ),
+            (
+                "/books/dune.pdf?token=abc",
+                "https://books.example.net:8443/books/dune.pdf?token=abc",
+            ),
+            (
+                "https://cdn.example.net/dune.epub",
+                "https://cdn.example.net/dune.epub",
+            ),
+        ] {
+            assert_eq!(with_slash.resolve(href).unwrap().as_str(), resolved);
+            assert_eq!(without_slash.resolve(href).unwrap().as_str(), resolved);
+        }
+
+        assert!(instance("books.example.net/opds").resolve("/x").is_err());
+    }
+
     /// Test parsing an OPDS entry for Frank Herbert's Dune.
     #[test]
     fn parse_entry() {
         let xml = include_str!("../tests/entry.xml");
-        let entry = quick_xml::de::from_str::<Entry>(&xml);
+        let entry = quick_xml::de::from_str::<Entry>(xml);
         assert!(entry.is_ok());
 
         let entry = entry.unwrap();
@@ -96,5 +1112,580 @@ mod tests {
             entry.published.unwrap(),
             DateTime::parse_from_rfc3339("1965-08-01T00:00:00+00:00").unwrap()
         );
+        assert_eq!(
+            entry.updated.unwrap(),
+            DateTime::parse_from_rfc3339("2024-03-17T21:22:03+00:00").unwrap()
+        );
+        let summary = &entry.summaries.unwrap()[0];
+        assert_eq!(summary.kind, TextKind::Text);
+        assert!(summary
+            .value
+            .starts_with("Frank Herbert's classic masterpiece"));
+    }
+
+    /// Test parsing the rights of an entry from either the Atom or Dublin Core element.
+    #[test]
+    fn parse_entry_rights() {
+        let xml = include_str!("../tests/entry-rights.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert_eq!(entry.rights.as_deref(), Some("Public domain in the USA."));
+
+        let xml = include_str!("../tests/entry-rights-both.xml");
+        let entry = super::parse_entry(xml).unwrap();
+        assert_eq!(entry.rights.as_deref(), Some("Public domain in the USA."));
+
+        let xml = r#"<entry xmlns:dc="http://purl.org/dc/terms/">
+            <title>Dune</title>
+            <id>urn:uuid:56e99d4d-bef9-445e-8162-35aaef306006</id>
+            <dc:rights>CC0 1.0</dc:rights>
+        </entry>"#;
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert_eq!(entry.rights.as_deref(), Some("CC0 1.0"));
+
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry.rights.is_none());
+    }
+
+    /// Test detecting whether an entry has a cover.
+    #[test]
+    fn entry_has_cover() {
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry.has_cover());
+
+        let xml = include_str!("../tests/entry-issued.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(!entry.has_cover());
+    }
+
+    /// Test that an entry's authors are parsed into a list whether it has one or several, even when
+    /// other elements appear between them.
+    #[test]
+    fn entry_authors() {
+        let names = |xml: &str| -> Vec<String> {
+            let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+            entry
+                .authors
+                .unwrap_or_default()
+                .into_iter()
+                .map(|author| author.name)
+                .collect()
+        };
+
+        assert_eq!(
+            names(include_str!("../tests/entry-author.xml")),
+            ["Ursula K. Le Guin"]
+        );
+        assert_eq!(
+            names(include_str!("../tests/entry-authors.xml")),
+            ["Terry Pratchett", "Neil Gaiman", "Peter Bowen"]
+        );
+
+        // Authors are found the same way when the entry is streamed from a feed.
+        let feed = format!(
+            "<feed>{}{}</feed>",
+            include_str!("../tests/entry-author.xml"),
+            include_str!("../tests/entry-authors.xml")
+        );
+        let mut authors = Vec::new();
+        stream_feed(feed.as_bytes(), |entry| {
+            authors.push(entry.authors.unwrap_or_default().len())
+        })
+        .unwrap();
+        assert_eq!(authors, [1, 3]);
+    }
+
+    /// Test that the acquisition link is chosen by the order of the preferred file types rather
+    /// than the order of the links, with the first link winning between links of the same type.
+    #[test]
+    fn entry_acquisition_link_order() {
+        let xml = include_str!("../tests/entry-formats.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let href = |preferred: &[&str]| {
+            let preferred: Vec<String> = preferred.iter().map(|s| s.to_string()).collect();
+            entry
+                .acquisition_link(&preferred)
+                .and_then(|link| link.href)
+        };
+
+        assert_eq!(
+            href(&["application/epub+zip", "application/pdf"]).as_deref(),
+            Some("/opds/download/80/epub/")
+        );
+        assert_eq!(
+            href(&["application/pdf", "application/epub+zip"]).as_deref(),
+            Some("/opds/download/80/pdf/")
+        );
+        // Sample links are never chosen, even when they're listed first.
+        assert_eq!(
+            href(&["application/x-cbz", "application/pdf"]).as_deref(),
+            Some("/opds/download/80/cbz/")
+        );
+        // Types the entry doesn't have are passed over.
+        assert_eq!(
+            href(&["application/x-mobipocket-ebook", "application/pdf"]).as_deref(),
+            Some("/opds/download/80/pdf/")
+        );
+        assert_eq!(href(&["application/x-mobipocket-ebook"]), None);
+        // Without any preference, the first acquisition link is chosen whatever its type.
+        assert_eq!(href(&[]).as_deref(), Some("/opds/download/80/pdf/"));
+    }
+
+    /// Test that acquisition links are matched by their base type, ignoring parameters.
+    #[test]
+    fn entry_parameterized_acquisition_link() {
+        let xml = include_str!("../tests/entry-parameterized.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let link = |preferred: &[&str]| {
+            let preferred: Vec<String> = preferred.iter().map(|s| s.to_string()).collect();
+            entry.acquisition_link(&preferred)
+        };
+
+        let epub = link(&["application/epub+zip", "application/pdf"]).unwrap();
+        assert_eq!(epub.href.as_deref(), Some("/opds/download/81/epub/"));
+        assert_eq!(epub.file_type.as_deref(), Some("application/epub+zip"));
+
+        let pdf = link(&["application/pdf"]).unwrap();
+        assert_eq!(pdf.href.as_deref(), Some("/opds/download/81/pdf/"));
+        assert_eq!(pdf.file_type.as_deref(), Some("application/pdf"));
+    }
+
+    /// Test that only the alternate link to the full catalog entry is chosen, whatever order its
+    /// type's parameters are in.
+    #[test]
+    fn entry_full_entry_link() {
+        let xml = include_str!("../tests/entry-alternates.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let link = entry.full_entry_link().unwrap();
+        assert_eq!(link.href.as_deref(), Some("/opds/entry/87"));
+
+        let full_entries: Vec<_> = entry
+            .links
+            .iter()
+            .flatten()
+            .filter(|link| link.is_full_entry())
+            .filter_map(|link| link.href.as_deref())
+            .collect();
+        assert_eq!(full_entries, ["/opds/related/87", "/opds/entry/87"]);
+
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry.full_entry_link().is_none());
+    }
+
+    /// Test that the description is taken from the summary or content as asked, with markup
+    /// converted to plain text unless it's kept.
+    #[test]
+    fn entry_description_source() {
+        let xml = include_str!("../tests/entry-descriptions.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let description = |source, html_to_text| entry.description(source, html_to_text).unwrap();
+
+        assert_eq!(
+            description(DescriptionSource::Summary, true),
+            "A short story.\nIn two paragraphs & a bit."
+        );
+        assert_eq!(
+            description(DescriptionSource::Summary, false),
+            "<p>A short story.</p><p>In two paragraphs &amp; a bit.</p>"
+        );
+        assert_eq!(
+            description(DescriptionSource::Content, true),
+            "The Time Traveller, for so it will be convenient to speak of him, was expounding a \
+             recondite matter to us.\nHis grey eyes shone\nand twinkled."
+        );
+        assert_eq!(
+            description(DescriptionSource::Content, false),
+            "<p>The Time Traveller, for so it will be convenient to speak of <em>him</em> , was \
+             expounding a recondite matter to us.</p> <p>His grey eyes shone <br/> and \
+             twinkled.</p>"
+        );
+        assert_eq!(
+            description(DescriptionSource::PreferText, false),
+            "A plain summary of The Time Machine."
+        );
+
+        // Sources fall back to each other, skipping empty elements.
+        let mut summaries_only = entry.clone();
+        summaries_only.contents = None;
+        assert_eq!(
+            summaries_only
+                .description(DescriptionSource::Content, true)
+                .unwrap(),
+            "A short story.\nIn two paragraphs & a bit."
+        );
+        let xml = include_str!("../tests/entry-formats.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry
+            .description(DescriptionSource::PreferText, true)
+            .is_none());
+    }
+
+    /// Test finding the ISBN among an entry's identifiers, whichever way it's given.
+    #[test]
+    fn parse_entry_isbn() {
+        let xml = include_str!("../tests/feed-identifiers.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        let isbns: Vec<_> = feed.entries.iter().map(Entry::isbn).collect();
+        assert_eq!(
+            isbns,
+            [
+                Some("9780441013593".to_string()),
+                Some("044100590X".to_string()),
+                None,
+            ]
+        );
+
+        let entry = &feed.entries[2];
+        assert_eq!(entry.identifier(IdentifierSource::Isbn), entry.id);
+        assert_eq!(entry.identifier(IdentifierSource::Id), entry.id);
+    }
+
+    /// Test that entries serialized to JSON, as feeds are cached, deserialize to the same entries.
+    #[test]
+    fn entry_json_round_trip() {
+        let fixtures = [
+            include_str!("../tests/entry-descriptions.xml"),
+            include_str!("../tests/entry-price.xml"),
+            include_str!("../tests/entry-alternates.xml"),
+            include_str!("../tests/entry-parameterized.xml"),
+        ];
+        for xml in fixtures {
+            let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+            let json = serde_json::to_string(&entry).unwrap();
+            let round_tripped: Entry = serde_json::from_str(&json).unwrap();
+            assert_eq!(serde_json::to_string(&round_tripped).unwrap(), json);
+            for source in [DescriptionSource::Summary, DescriptionSource::Content] {
+                assert_eq!(
+                    round_tripped.description(source, false),
+                    entry.description(source, false)
+                );
+            }
+        }
+
+        let feed = read_feed(include_str!("../tests/feed-series.xml").as_bytes()).unwrap();
+        let json = serde_json::to_string(&feed).unwrap();
+        let round_tripped: Feed = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&round_tripped).unwrap(), json);
+        let series = round_tripped.entries[1].series().unwrap();
+        assert_eq!(series.name, "Dune Chronicles");
+        assert_eq!(series.position, Some(2.0));
+    }
+
+    /// Test converting HTML descriptions to plain text.
+    #[test]
+    fn html_descriptions_to_plain_text() {
+        assert_eq!(
+            html_to_plain_text(
+                "<div><h1>Dune</h1><p>A <b>desert</b> planet&#8230; &lt;Arrakis&gt;</p></div>"
+            ),
+            "Dune\nA desert planet\u{2026} <Arrakis>"
+        );
+        assert_eq!(
+            html_to_plain_text("Tom &amp Jerry & co &#x41;"),
+            "Tom &amp Jerry & co A"
+        );
+        assert_eq!(html_to_plain_text("unclosed <b"), "unclosed <b");
+        assert_eq!(html_to_plain_text("<p>One\r\n  line</p>"), "One line");
+    }
+
+    /// Test that the type of acquisition links without one is inferred from their href.
+    #[test]
+    fn entry_untyped_acquisition_link() {
+        let xml = include_str!("../tests/entry-untyped.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let link = |preferred: &[&str]| {
+            let preferred: Vec<String> = preferred.iter().map(|s| s.to_string()).collect();
+            entry.acquisition_link(&preferred)
+        };
+
+        let epub = link(&["application/epub+zip", "application/pdf"]).unwrap();
+        assert_eq!(epub.href.as_deref(), Some("/opds/download/79/epub/"));
+        assert_eq!(epub.file_type.as_deref(), Some("application/epub+zip"));
+
+        let pdf = link(&["application/pdf"]).unwrap();
+        assert_eq!(pdf.href.as_deref(), Some("/books/dune.pdf?token=abc"));
+        assert_eq!(pdf.file_type.as_deref(), Some("application/pdf"));
+
+        assert!(link(&["application/x-cbz"]).is_none());
+
+        // Typed links are always preferred over untyped ones.
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let link = entry
+            .acquisition_link(&["application/epub+zip".to_string()])
+            .unwrap();
+        assert_eq!(link.href.as_deref(), Some("/opds/download/79/epub/"));
+    }
+
+    /// Test reading whether entries were read from their page streaming links.
+    #[test]
+    fn parse_entry_read_status() {
+        let xml = include_str!("../tests/feed-read-status.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        let read: Vec<_> = feed.entries.iter().map(Entry::is_read).collect();
+        assert_eq!(read, [Some(true), Some(false), None, None]);
+    }
+
+    /// Test parsing the titles of acquisition links, which are optional.
+    #[test]
+    fn parse_link_titles() {
+        let xml = include_str!("../tests/entry-link-titles.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let titles: Vec<_> = entry
+            .links
+            .iter()
+            .flatten()
+            .map(|link| link.title.as_deref())
+            .collect();
+        assert_eq!(titles, [Some("Deluxe Edition"), Some("  "), None]);
+    }
+
+    /// Test parsing the price of an entry's buy link.
+    #[test]
+    fn parse_entry_price() {
+        let xml = include_str!("../tests/entry-price.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        let price = entry.price().unwrap();
+        assert_eq!(price.amount, "9.99");
+        assert_eq!(price.currency_code.as_deref(), Some("USD"));
+        assert_eq!(price.to_string(), "9.99 USD");
+        assert!(entry.acquisition_link(&["text/html".to_string()]).is_none());
+
+        let xml = include_str!("../tests/entry-prices.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert_eq!(entry.price().unwrap().to_string(), "8.99 EUR");
+
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry.price().is_none());
+    }
+
+    /// Test parsing the availability of each entry's acquisition link, with entries that don't
+    /// give one treated as available.
+    #[test]
+    fn parse_entry_availability() {
+        let xml = include_str!("../tests/feed-availability.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        let file_types = ["application/epub+zip".to_string()];
+        let statuses: Vec<_> = feed
+            .entries
+            .iter()
+            .map(|entry| {
+                let link = entry.acquisition_link(&file_types).unwrap();
+                (
+                    link.availability
+                        .as_ref()
+                        .map(|availability| availability.status),
+                    link.is_available(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (Some(AvailabilityStatus::Available), true),
+                (Some(AvailabilityStatus::Unavailable), false),
+                (Some(AvailabilityStatus::Reserved), false),
+                (Some(AvailabilityStatus::Ready), true),
+                (Some(AvailabilityStatus::Unknown), true),
+                (None, true),
+            ]
+        );
+    }
+
+    /// Test parsing the series each entry of a feed belongs to.
+    #[test]
+    fn parse_entry_series() {
+        let xml = include_str!("../tests/feed-series.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        let series: Vec<_> = feed
+            .entries
+            .iter()
+            .map(|entry| entry.series().unwrap())
+            .collect();
+        assert_eq!(series[0].name, "Dune Chronicles");
+        assert_eq!(series[0].position, Some(1.0));
+        assert_eq!(series[1].position, Some(2.0));
+
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry.series().is_none());
+    }
+
+    /// Test that Calibre's series metadata is used when there's no standard series, and that the
+    /// standard series is preferred when an entry has both.
+    #[test]
+    fn parse_entry_calibre_series() {
+        let xml = include_str!("../tests/feed-calibre-series.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        let series: Vec<_> = feed.entries.iter().map(|entry| entry.series()).collect();
+
+        let calibre = series[0].as_ref().unwrap();
+        assert_eq!(calibre.name, "Discworld");
+        assert_eq!(calibre.position, Some(1.5));
+        let unnumbered = series[1].as_ref().unwrap();
+        assert_eq!(unnumbered.name, "Discworld");
+        assert_eq!(unnumbered.position, None);
+        let standard = series[2].as_ref().unwrap();
+        assert_eq!(standard.name, "Dune Chronicles");
+        assert_eq!(standard.position, Some(1.0));
+        assert!(series[3].is_none());
+    }
+
+    /// Test that the original publication date is preferred over the catalog's published date.
+    #[test]
+    fn parse_entry_issued() {
+        let xml = include_str!("../tests/entry-issued.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert_eq!(entry.published.unwrap().year(), 2024);
+        assert_eq!(entry.issued.unwrap().year(), 1965);
+        assert_eq!(entry.year(), Some(1965));
+
+        // Without an issued date, the published date is used.
+        let xml = include_str!("../tests/entry.xml");
+        let entry = quick_xml::de::from_str::<Entry>(xml).unwrap();
+        assert!(entry.issued.is_none());
+        assert_eq!(entry.year(), Some(1965));
+    }
+
+    /// Test parsing a paginated feed with a feed-level updated timestamp.
+    #[test]
+    fn parse_paginated_feed() {
+        let xml = include_str!("../tests/feed.xml");
+        let feed = quick_xml::de::from_str::<Feed>(xml).unwrap();
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(feed.entries[1].title, "Children of Dune");
+        assert_eq!(
+            feed.feed_updated.unwrap(),
+            DateTime::parse_from_rfc3339("2024-03-18T09:15:00+00:00").unwrap()
+        );
+        assert!(feed
+            .links
+            .iter()
+            .any(|link| link.rel == Some(LinkType::Next)));
+    }
+
+    /// Test that entries missing their title or id are parsed leniently, and named by the strict
+    /// check.
+    #[test]
+    fn feed_missing_required_elements() {
+        let mut feed = read_feed(include_str!("../tests/feed-missing.xml").as_bytes()).unwrap();
+        assert_eq!(feed.entries.len(), 3);
+        assert_eq!(feed.entries[1].title, "");
+        assert_eq!(feed.entries[2].id, "");
+
+        let err = feed.check_required().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "entry 2 (urn:uuid:7a8b9c0d-1e2f-4a3b-9c4d-5e6f7a8b9c0d) has no title"
+        );
+
+        feed.entries.remove(1);
+        let err = feed.check_required().unwrap_err();
+        assert_eq!(err.to_string(), "entry 2 ('Hyperion') has no id");
+
+        feed.entries.remove(1);
+        assert!(feed.check_required().is_ok());
+    }
+
+    /// Test parsing a feed prefixed with a byte order mark and without an XML declaration.
+    #[test]
+    fn parse_feed_with_bom() {
+        let xml = include_str!("../tests/feed-bom.xml");
+        assert!(xml.starts_with('\u{feff}'));
+
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(feed.entries[0].title, "Dune");
+        assert_eq!(feed.entries[1].title, "Children of Dune");
+    }
+
+    /// Test parsing a feed whose elements all carry a namespace prefix.
+    #[test]
+    fn parse_namespaced_feed() {
+        let xml = include_str!("../tests/feed-namespaced.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(feed.entries[0].title, "Dune");
+        assert_eq!(
+            feed.entries[0].authors.as_ref().unwrap()[0].name,
+            "Frank Herbert"
+        );
+        assert!(feed.entries[0].published.is_some());
+
+        let links = feed.entries[1].links.as_ref().unwrap();
+        assert_eq!(links[0].rel, Some(LinkType::Acquisition));
+        assert_eq!(links[0].href.as_deref(), Some("/opds/download/81/epub/"));
+        assert_eq!(feed.links[0].rel, Some(LinkType::Next));
+        assert!(feed.feed_updated.is_some());
+        assert_eq!(feed.page_count(), None);
+    }
+
+    /// Test that entries nested in groups are collected along with the feed's own entries.
+    #[test]
+    fn parse_grouped_feed() {
+        let xml = include_str!("../tests/feed-groups.xml");
+        let feed = read_feed(xml.as_bytes()).unwrap();
+        let mut titles: Vec<_> = feed
+            .entries
+            .iter()
+            .map(|entry| entry.title.as_str())
+            .collect();
+        titles.sort();
+        assert_eq!(
+            titles,
+            [
+                "Children of Dune",
+                "Dune",
+                "Dune Messiah",
+                "God Emperor of Dune"
+            ]
+        );
+        assert_eq!(feed.links.len(), 1);
+    }
+
+    /// Test streaming a large feed, passing entries along one at a time.
+    #[test]
+    fn stream_large_feed() {
+        let entry = include_str!("../tests/entry.xml");
+        let mut xml = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+            <updated>2024-03-18T09:15:00+00:00</updated>
+            <link rel="next" href="/opds/new?offset=5000"/>"#,
+        );
+        for _ in 0..5000 {
+            xml.push_str(entry);
+        }
+        xml.push_str("</feed>");
+
+        let mut count = 0;
+        let feed = stream_feed(xml.as_bytes(), |entry| {
+            assert_eq!(entry.title, "Dune");
+            assert_eq!(entry.links.unwrap().len(), 3);
+            count += 1;
+        })
+        .unwrap();
+
+        assert_eq!(count, 5000);
+        assert!(feed.entries.is_empty());
+        assert!(feed.feed_updated.is_some());
+        assert_eq!(feed.links[0].rel, Some(LinkType::Next));
+    }
+
+    /// Test that dates are parsed leniently.
+    #[test]
+    fn parse_lenient_dates() {
+        let expected = DateTime::parse_from_rfc3339("2021-06-01T00:00:00+00:00").unwrap();
+        assert_eq!(parse_date("2021-06-01T00:00:00Z").unwrap(), expected);
+        assert_eq!(parse_date("2021-06-01T00:00:00").unwrap(), expected);
+        assert_eq!(parse_date(" 2021-06-01 ").unwrap(), expected);
+        assert_eq!(
+            parse_date("2021").unwrap(),
+            DateTime::parse_from_rfc3339("2021-01-01T00:00:00+00:00").unwrap()
+        );
+        assert!(parse_date("June 2021").is_none());
     }
 }
@@ -1,5 +1,7 @@
 //! Helper functions for interacting with the Plato e-reader software.
 
+use std::path::Path;
+
 use serde_json::json;
 
 /// Show a notification on the device with the given `message`.
@@ -31,3 +33,38 @@ pub fn add_document(doc: serde_json::Value) {
     });
     println!("{event}");
 }
+
+/// Remove the document at `path`, relative to the library, from the device's library. Used when
+/// a document has been moved, before it's added again at its new path.
+pub fn remove_document(path: &Path) {
+    let event = json!({
+        "type": "removeDocument",
+        "path": path,
+    });
+    println!("{event}");
+}
+
+/// Ask Plato to import new documents into the current library, rescanning its directory for files
+/// that aren't in the library yet.
+pub fn refresh_library() {
+    println!("{}", import_event());
+}
+
+/// The event that makes Plato import new documents, as described in Plato's
+/// [hooks documentation](https://github.com/baskerville/plato/blob/master/doc/HOOKS.md).
+fn import_event() -> serde_json::Value {
+    json!({
+        "type": "import",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that the library refresh is requested with Plato's import event.
+    #[test]
+    fn refresh_library_event() {
+        assert_eq!(import_event().to_string(), r#"{"type":"import"}"#);
+    }
+}
@@ -0,0 +1,1275 @@
+//! Contains the settings for the application and how they're loaded.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{format_err, Context, Error};
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    base_mime_type, extension_for_mime_type, messages::Messages, mime_type_for_extension,
+    opds::Instance, EXTRA_MIME_TYPE_EXTENSIONS, FILE_TYPE_MIME_TYPES,
+};
+
+/// The length of the UUIDs documents outside of a series are named after.
+const UUID_LENGTH: usize = 36;
+
+/// The settings files that are looked for, in order of preference.
+const SETTINGS_PATHS: [&str; 4] = [
+    "Settings.toml",
+    "Settings.json",
+    "Settings.yaml",
+    "Settings.yml",
+];
+
+/// Brief descriptions of each setting, written above it when printing the default config.
+const SETTING_DESCRIPTIONS: &[(&str, &str)] = &[
+    (
+        "servers",
+        "Mapping of server names to the OPDS feeds they're synced from.",
+    ),
+    (
+        "secrets-path",
+        "A file of server credentials merged over those in the servers table.",
+    ),
+    (
+        "preferred-file-types",
+        "File types to download, in order of preference. Empty downloads any file type.",
+    ),
+    (
+        "use-server-name-directories",
+        "Save documents in a directory named after their server.",
+    ),
+    (
+        "organize-by-file-type",
+        "Save documents in directories named in `organization`.",
+    ),
+    (
+        "organization",
+        "Mapping of file extensions to the directories they're saved in.",
+    ),
+    (
+        "combined-notification",
+        "Combine download notifications into a single summary.",
+    ),
+    (
+        "incremental-sync",
+        "Skip servers whose feed hasn't been updated since the last sync.",
+    ),
+    (
+        "up-to-date-exit-code",
+        "The exit code used when there was nothing to download.",
+    ),
+    (
+        "file-name-max-length",
+        "The maximum length of a downloaded file's name in bytes.",
+    ),
+    (
+        "pagination-prefetch",
+        "The number of feed pages to fetch ahead. Zero disables it.",
+    ),
+    ("dithered", "Render synced documents with dithering."),
+    (
+        "max-redirects",
+        "The maximum number of redirects a request will follow.",
+    ),
+    (
+        "notification-level",
+        "How much detail notifications go into: normal or verbose.",
+    ),
+    ("pre-sync-command", "A shell command to run before syncing."),
+    ("post-sync-command", "A shell command to run after syncing."),
+    (
+        "after-download-command",
+        "A shell command to run on each downloaded document, with {path} as its path.",
+    ),
+    (
+        "cleanup-empty-dirs",
+        "Remove empty server and organization directories after syncing.",
+    ),
+    (
+        "move-reorganized-documents",
+        "Move documents whose path changed, which resets their reading progress in Plato.",
+    ),
+    (
+        "organize-by-date",
+        "Save documents in directories named after their published date.",
+    ),
+    (
+        "date-directory-format",
+        "The chrono format string used to name date directories.",
+    ),
+    (
+        "max-write-buffer",
+        "Bytes held in memory waiting to be written. Zero disables it.",
+    ),
+    ("require-cover", "Skip entries without a cover image."),
+    (
+        "skip-unavailable",
+        "Skip entries a library marks as unavailable or reserved.",
+    ),
+    ("skip-read", "Skip entries the server says have been read."),
+    (
+        "pool-max-idle-per-host",
+        "The maximum number of idle connections kept per server.",
+    ),
+    (
+        "pool-idle-timeout-secs",
+        "How long idle connections are kept open, in seconds.",
+    ),
+    (
+        "tcp-keepalive-secs",
+        "The interval between TCP keep-alive probes. Zero disables them.",
+    ),
+    (
+        "max-retry-after-secs",
+        "The longest to wait before retrying a rate limited request.",
+    ),
+    (
+        "failed-retry-cooldown-hours",
+        "How long to skip entries that failed to download for. Zero always retries them.",
+    ),
+    (
+        "added-timestamp-source",
+        "The date documents are added with: now, published or updated.",
+    ),
+    (
+        "organize-by-series",
+        "Save books in a series in a directory named after the series.",
+    ),
+    (
+        "staging-path",
+        "A directory documents are downloaded into before being moved.",
+    ),
+    (
+        "plato-library-root",
+        "The library root added documents' paths are relative to, instead of Plato's.",
+    ),
+    (
+        "stop-at-last-page",
+        "Stop crawling a paginated feed at the page its last link points to.",
+    ),
+    (
+        "feed-cache-ttl-secs",
+        "How long to reuse a crawled feed for instead of crawling it again. Zero disables it.",
+    ),
+    (
+        "format-priority",
+        "File types to download for books, comics and documents, in order of preference.",
+    ),
+    (
+        "default-extension-for-unknown",
+        "The extension of files whose MIME type doesn't have a known extension.",
+    ),
+    (
+        "refresh-after-sync",
+        "Ask Plato to import new documents once syncing has finished.",
+    ),
+    (
+        "max-downloads-per-sync",
+        "The maximum number of documents downloaded from each server in a sync.",
+    ),
+    (
+        "max-run-duration-secs",
+        "The longest a sync may spend downloading before stopping, in seconds.",
+    ),
+    (
+        "error-dump-path",
+        "The file errors are written to. Leave empty to disable it.",
+    ),
+    (
+        "prefer-open-access",
+        "Download open-access copies of books over any other acquisition link.",
+    ),
+    (
+        "min-file-size-bytes",
+        "Downloads smaller than this many bytes are discarded as broken.",
+    ),
+    (
+        "auto-decompress",
+        "Decompress downloads that a server wrapped in an extra layer of gzip.",
+    ),
+    (
+        "fetch-full-entries",
+        "Fetch the complete catalog entry of books that feeds only partially describe.",
+    ),
+    (
+        "embed-metadata",
+        "Write the catalog's title, authors, series and summary into downloaded EPUBs.",
+    ),
+    (
+        "description-source",
+        "Where descriptions are taken from: summary, content or prefer-text.",
+    ),
+    (
+        "description-html-to-text",
+        "Convert HTML and XHTML descriptions to plain text.",
+    ),
+    (
+        "identifier-source",
+        "The identifier documents are added with: id, uuid or isbn.",
+    ),
+    (
+        "mime-overrides",
+        "MIME types servers mislabel files with, mapped to the file type to treat them as.",
+    ),
+    (
+        "messages",
+        "Templates replacing the built-in notification messages, i.e. to translate them.",
+    ),
+];
+
+/// Holds the settings for the application converted from a TOML, JSON or YAML file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Mapping of server names to their respective [Instance] settings.
+    pub servers: HashMap<String, Instance>,
+    /// Optional TOML, JSON or YAML file mapping server names to their credentials, which replace
+    /// those given in `servers`. Lets the settings be shared without them. Ignored if the file
+    /// doesn't exist.
+    pub secrets_path: Option<PathBuf>,
+    /// List of preferred file types to download (i.e. application/x-cbz or application/pdf). An
+    /// empty list downloads any file type.
+    pub preferred_file_types: Vec<String>,
+    /// Whether files should be placed in a directory named after the server they have been pulled
+    /// from.
+    pub use_server_name_directories: bool,
+    /// Whether files should be placed in directories based on their file type, giving more
+    /// organization in Plato's UI. How these folders should be mapped to a name can be
+    /// configured in the `organization` table.
+    pub organize_by_file_type: bool,
+    /// Mapping of file extensions to directory names. Used when `organize_by_file_type` is true.
+    /// Key's are file extensions and values are the directory names they should be placed in.
+    ///
+    /// ## Example
+    /// ```toml
+    /// [organization]
+    /// epub = "Books"
+    /// cbz = "Manga"
+    /// pdf = "Documents"
+    /// ```
+    pub organization: HashMap<String, String>,
+    /// Whether the per-file-type download notifications should be combined into a single summary
+    /// line, i.e. "Downloading 5 documents from 'server' (3 EPUB, 2 CBZ)".
+    pub combined_notification: bool,
+    /// Whether servers whose feed `<updated>` timestamp hasn't advanced since the last successful
+    /// sync should be skipped without crawling or downloading anything.
+    pub incremental_sync: bool,
+    /// The exit code used when a run finds nothing to download on any server and runs into no
+    /// errors, letting wrapper scripts tell a no-op run apart from one that synced documents.
+    /// Runs whose downloads failed, were declined or were cut short aren't up to date.
+    pub up_to_date_exit_code: u8,
+    /// The maximum length of a downloaded file's name in bytes. Longer names are truncated while
+    /// keeping their extension, as FAT32 storage can't hold names longer than 255 bytes. It has
+    /// to fit a UUID file name with the longest extension.
+    pub file_name_max_length: usize,
+    /// The number of pages of a paginated feed to fetch ahead while the current page is being
+    /// parsed. Zero disables prefetching.
+    pub pagination_prefetch: usize,
+    /// Whether synced documents should be rendered with dithering in Plato.
+    pub dithered: bool,
+    /// The maximum number of redirects a single request will follow before failing.
+    pub max_redirects: usize,
+    /// How much detail the notifications shown during a sync should go into.
+    pub notification_level: NotificationLevel,
+    /// A shell command to run before syncing, i.e. to mount a drive.
+    pub pre_sync_command: Option<String>,
+    /// A shell command to run after syncing, even if syncing failed.
+    pub post_sync_command: Option<String>,
+    /// A shell command to run on each downloaded document once it's in place, with `{path}`
+    /// replaced by its quoted path. Documents the command fails on are deleted rather than added
+    /// to the library, so a later sync downloads them again.
+    pub after_download_command: Option<String>,
+    /// Whether empty server name and organization directories should be removed after syncing.
+    pub cleanup_empty_dirs: bool,
+    /// Whether documents whose path changed with the organization settings should be moved to
+    /// their new path. Plato has no event for moving a document, so it's removed and added
+    /// again, losing its reading progress. Otherwise documents stay at their old path.
+    pub move_reorganized_documents: bool,
+    /// Whether files should be placed in directories based on the date they were published.
+    /// Entries without a date are placed in an `Undated` directory.
+    pub organize_by_date: bool,
+    /// The [chrono format string](https://docs.rs/chrono/latest/chrono/format/strftime/index.html)
+    /// used to name date directories when `organize_by_date` is true. Use `/` to nest folders.
+    pub date_directory_format: String,
+    /// The maximum number of downloaded bytes held in memory waiting to be written to storage.
+    /// When non-zero, downloading and writing happen side by side so slow storage doesn't stall
+    /// the network, and downloading pauses whenever this much data is waiting to be written.
+    /// Zero writes data as soon as it's downloaded.
+    pub max_write_buffer: usize,
+    /// Whether entries without a cover or thumbnail image should be skipped.
+    pub require_cover: bool,
+    /// Whether entries a library marks as unavailable or reserved with `<opds:availability>`,
+    /// i.e. because every copy is on loan, should be skipped rather than failing to download.
+    /// Entries without availability information are treated as available.
+    pub skip_unavailable: bool,
+    /// Whether entries the server says have been read should be skipped. Only servers that add
+    /// page streaming links with the last page read, such as Komga, say so. Entries without a
+    /// read status are always downloaded.
+    pub skip_read: bool,
+    /// The maximum number of idle connections kept open to each server for reuse. Unset keeps
+    /// every idle connection open until it times out.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept open for reuse, in seconds.
+    pub pool_idle_timeout_secs: u64,
+    /// The interval between TCP keep-alive probes on open connections, in seconds. Zero disables
+    /// keep-alive probes.
+    pub tcp_keepalive_secs: u64,
+    /// The longest a request will wait before being retried when a server responds with
+    /// `429 Too Many Requests` and a `Retry-After` header, in seconds. Longer waits are cut short.
+    pub max_retry_after_secs: u64,
+    /// How long an entry whose document failed to download is skipped for before it's retried,
+    /// in hours. Zero retries failed entries on every sync.
+    pub failed_retry_cooldown_hours: u64,
+    /// Which date the `added` timestamp of synced documents is taken from, which Plato can sort
+    /// the library by. Documents whose entry doesn't have the chosen date use the sync time.
+    pub added_timestamp_source: AddedTimestampSource,
+    /// Whether books that are part of a series should be placed in a directory named after the
+    /// series and named after their position in it, i.e. `Dune Chronicles/02 - Dune Messiah.epub`.
+    /// Books without a series are organized as usual.
+    pub organize_by_series: bool,
+    /// A directory documents are downloaded into before being moved into the save path once
+    /// every download from a server has finished, i.e. fast local storage when the library is on
+    /// slow storage. Unset downloads documents straight into the save path.
+    pub staging_path: Option<PathBuf>,
+    /// The root of Plato's library that the paths of added documents are made relative to, in
+    /// place of the library path Plato passes to the hook. Unset uses Plato's library path.
+    pub plato_library_root: Option<PathBuf>,
+    /// Whether crawling a paginated feed should stop at the page its `last` link points to, even
+    /// if that page links to a next page.
+    pub stop_at_last_page: bool,
+    /// How long the feed of a server is cached for after it's been crawled, in seconds. Syncs
+    /// within that time read the feed from the cache instead of crawling the server again. Zero
+    /// disables the cache.
+    pub feed_cache_ttl_secs: u64,
+    /// File types to download for each category of document, in order of preference. An entry's
+    /// category is taken from the first of its acquisition links with a known file type. Entries
+    /// whose category isn't listed, or that offer none of its file types, use
+    /// `preferred_file_types`.
+    pub format_priority: HashMap<FormatCategory, Vec<String>>,
+    /// The extension files are saved with when their MIME type doesn't have a known extension.
+    pub default_extension_for_unknown: String,
+    /// Whether Plato should be asked to import new documents into its library once syncing has
+    /// finished, in case it doesn't pick up every added document by itself.
+    pub refresh_after_sync: bool,
+    /// The maximum number of documents downloaded from each server in a single sync. Servers can
+    /// override it with their own `max-downloads`. Unset doesn't limit downloads.
+    pub max_downloads_per_sync: Option<usize>,
+    /// The longest a sync may run for, in seconds. Once it's been exceeded, the download in
+    /// progress is finished and no more are started, leaving the rest for the next sync. Unset
+    /// doesn't limit how long syncs run for.
+    pub max_run_duration_secs: Option<u64>,
+    /// The file the error a run failed with is written to. An empty path disables writing it.
+    pub error_dump_path: String,
+    /// Whether an entry's open-access links should be chosen over its other acquisition links,
+    /// so free copies are downloaded whenever the catalog offers one. Open-access links aren't
+    /// downloaded otherwise.
+    pub prefer_open_access: bool,
+    /// The smallest size in bytes a downloaded document can be. Smaller downloads are removed
+    /// instead of being added to the library, as some servers respond to missing files with an
+    /// empty body.
+    pub min_file_size_bytes: u64,
+    /// Whether downloads that a server wrapped in an extra layer of gzip, which stops them from
+    /// opening, should be decompressed. Decompressed downloads are only kept if they're a ZIP
+    /// archive, as EPUBs and CBZs are.
+    pub auto_decompress: bool,
+    /// Whether entries with an alternate link to their complete catalog entry should have it
+    /// fetched, for catalogs whose feeds leave out metadata such as the series. The partial entry
+    /// is used if the complete one can't be fetched.
+    pub fetch_full_entries: bool,
+    /// Whether the title, authors, series and summary from the catalog should be written into the
+    /// package document of downloaded EPUBs, so they travel with the file. EPUBs that can't be
+    /// rewritten are kept as downloaded.
+    pub embed_metadata: bool,
+    /// Which of an entry's summary and content the description of the book is taken from.
+    pub description_source: DescriptionSource,
+    /// Whether descriptions in HTML or XHTML should be converted to plain text, as Plato doesn't
+    /// render markup.
+    pub description_html_to_text: bool,
+    /// Which of an entry's identifiers documents are added to the library with.
+    pub identifier_source: IdentifierSource,
+    /// MIME types mapped to the file type links declaring them are treated as, for servers that
+    /// label files with the wrong type, i.e. EPUBs as `application/octet-stream`. File types can
+    /// be MIME types or short names like `epub`.
+    pub mime_overrides: HashMap<String, String>,
+    /// Templates replacing the built-in notification messages.
+    pub messages: Messages,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            servers: HashMap::new(),
+            secrets_path: None,
+            preferred_file_types: vec!["application/epub+zip".to_string()],
+            use_server_name_directories: true,
+            organize_by_file_type: true,
+            organization: {
+                let mut map = HashMap::new();
+                map.insert("epub".to_string(), "Books".to_string());
+                map.insert("cbz".to_string(), "Comics".to_string());
+                map.insert("pdf".to_string(), "Documents".to_string());
+                map
+            },
+            combined_notification: false,
+            incremental_sync: false,
+            up_to_date_exit_code: 0,
+            file_name_max_length: 255,
+            pagination_prefetch: 0,
+            dithered: false,
+            max_redirects: 10,
+            notification_level: NotificationLevel::Normal,
+            pre_sync_command: None,
+            post_sync_command: None,
+            after_download_command: None,
+            cleanup_empty_dirs: false,
+            move_reorganized_documents: false,
+            organize_by_date: false,
+            date_directory_format: "%Y/%m".to_string(),
+            max_write_buffer: 0,
+            require_cover: false,
+            skip_unavailable: false,
+            skip_read: false,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout_secs: 90,
+            tcp_keepalive_secs: 0,
+            max_retry_after_secs: 60,
+            failed_retry_cooldown_hours: 0,
+            added_timestamp_source: AddedTimestampSource::Now,
+            organize_by_series: false,
+            staging_path: None,
+            plato_library_root: None,
+            stop_at_last_page: false,
+            feed_cache_ttl_secs: 0,
+            format_priority: HashMap::new(),
+            default_extension_for_unknown: "bin".to_string(),
+            refresh_after_sync: false,
+            max_downloads_per_sync: None,
+            max_run_duration_secs: None,
+            error_dump_path: "opds_error.txt".to_string(),
+            prefer_open_access: false,
+            min_file_size_bytes: 1,
+            auto_decompress: false,
+            fetch_full_entries: false,
+            embed_metadata: false,
+            description_source: DescriptionSource::Summary,
+            identifier_source: IdentifierSource::Id,
+            description_html_to_text: true,
+            mime_overrides: HashMap::new(),
+            messages: Messages::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Normalize settings that accept more than one form, erroring on values that can't be
+    /// understood.
+    pub fn normalize(&mut self) -> Result<(), Error> {
+        // File types can be given as short names like `epub`, which are resolved to the MIME
+        // types used by acquisition links.
+        self.preferred_file_types =
+            normalize_file_types(&self.preferred_file_types, "preferred-file-types")?;
+        for file_types in self.format_priority.values_mut() {
+            *file_types = normalize_file_types(file_types, "format-priority")?;
+        }
+        self.mime_overrides = self
+            .mime_overrides
+            .iter()
+            .map(|(mime_type, file_type)| {
+                let mut file_types =
+                    normalize_file_types(std::slice::from_ref(file_type), "mime-overrides")?;
+                Ok((base_mime_type(mime_type), file_types.remove(0)))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        // The extension ends up in file names, so it can't be allowed to nest directories.
+        self.default_extension_for_unknown = self
+            .default_extension_for_unknown
+            .trim()
+            .trim_start_matches('.')
+            .to_string();
+        if self.default_extension_for_unknown.is_empty()
+            || self.default_extension_for_unknown.contains(['/', '\\'])
+        {
+            return Err(format_err!(
+                "invalid default-extension-for-unknown: {}",
+                self.default_extension_for_unknown
+            ));
+        }
+
+        // Documents outside of a series are named after their UUID, which can't be truncated
+        // without losing what keeps names apart, so the limit has to fit one with any extension.
+        let longest_extension = FILE_TYPE_MIME_TYPES
+            .iter()
+            .map(|(extension, _)| extension.len())
+            .chain(
+                EXTRA_MIME_TYPE_EXTENSIONS
+                    .iter()
+                    .map(|(_, extension)| extension.len()),
+            )
+            .chain([self.default_extension_for_unknown.len()])
+            .max()
+            .unwrap_or_default();
+        let min_file_name_length = UUID_LENGTH + 1 + longest_extension;
+        if self.file_name_max_length < min_file_name_length {
+            return Err(format_err!(
+                "file-name-max-length has to be at least {} to fit a UUID and its extension",
+                min_file_name_length
+            ));
+        }
+
+        // Server URLs are checked up front, so a typo fails before anything is synced.
+        for (name, instance) in &mut self.servers {
+            instance.url = instance.url.trim().to_string();
+            Url::parse(&instance.url)
+                .with_context(|| format!("invalid url for server '{}'", name))?;
+
+            // Basic authentication joins the username and password with a colon, so only the
+            // password can contain one.
+            let username = instance.username.as_deref().unwrap_or_default();
+            if instance.login.is_none() && username.contains(':') {
+                return Err(format_err!(
+                    "the username for server '{}' can't contain ':' with basic authentication",
+                    name
+                ));
+            }
+        }
+
+        self.messages.validate()?;
+
+        // Formatting a date with an invalid format string panics, so catch it up front.
+        if StrftimeItems::new(&self.date_directory_format).any(|item| item == Item::Error) {
+            return Err(format_err!(
+                "invalid date-directory-format: {}",
+                self.date_directory_format
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Warnings about settings that are valid but likely mistakes, which don't stop a sync.
+    ///
+    /// When organizing by file type, this cross-checks `organization` with the file types that
+    /// can be downloaded. Unused directories are only warned about if `organization` was
+    /// changed, as the default one covers file types that aren't downloaded by default.
+    pub fn warnings(&self) -> Vec<String> {
+        if !self.organize_by_file_type {
+            return Vec::new();
+        }
+
+        let downloaded: BTreeSet<&str> = self
+            .preferred_file_types
+            .iter()
+            .chain(self.format_priority.values().flatten())
+            .chain(self.mime_overrides.values())
+            .map(|mime_type| {
+                extension_for_mime_type(mime_type).unwrap_or(&self.default_extension_for_unknown)
+            })
+            .collect();
+        let organized: BTreeSet<&str> = self.organization.keys().map(String::as_str).collect();
+
+        let mut warnings = Vec::new();
+        // Any file type can be downloaded when none are preferred.
+        let any_file_type = self.preferred_file_types.is_empty();
+        if self.organization != Settings::default().organization && !any_file_type {
+            for extension in organized.difference(&downloaded) {
+                warnings.push(format!(
+                    "organization has a directory for {} files, but preferred-file-types never \
+                     downloads them",
+                    extension
+                ));
+            }
+        }
+        for extension in downloaded.difference(&organized) {
+            warnings.push(format!(
+                "{} files are downloaded but organization has no directory for them, so they're \
+                 saved outside of the organization directories",
+                extension
+            ));
+        }
+        warnings
+    }
+}
+
+/// Resolve the short file type names in `file_types` to MIME types, leaving MIME types as they
+/// are. The name of the `setting` the file types came from is used in errors.
+fn normalize_file_types(file_types: &[String], setting: &str) -> Result<Vec<String>, Error> {
+    file_types
+        .iter()
+        .map(|file_type| {
+            if file_type.contains('/') {
+                return Ok(file_type.clone());
+            }
+
+            mime_type_for_extension(file_type)
+                .map(str::to_string)
+                .ok_or_else(|| format_err!("unknown file type in {}: {}", setting, file_type))
+        })
+        .collect()
+}
+
+/// Coarse categories of documents, used to prefer different file types for each.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FormatCategory {
+    /// Books, such as EPUB and MOBI files.
+    Book,
+    /// Comics and manga, such as CBZ and CBR files.
+    Comic,
+    /// Documents, such as PDF and DjVu files.
+    Document,
+}
+
+impl FormatCategory {
+    /// The category documents of the given MIME type belong to, if it's known.
+    pub fn of_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type {
+            "application/epub+zip"
+            | "application/x-mobipocket-ebook"
+            | "application/x-fictionbook+xml"
+            | "text/plain" => Some(FormatCategory::Book),
+            "application/x-cbz" | "application/x-cbr" => Some(FormatCategory::Comic),
+            "application/pdf" | "image/vnd.djvu" => Some(FormatCategory::Document),
+            _ => None,
+        }
+    }
+}
+
+/// How much detail the notifications shown during a sync go into.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationLevel {
+    /// Only notify about the progress of each server.
+    Normal,
+    /// Also include statistics and details useful for diagnosing problems.
+    Verbose,
+}
+
+/// Which element of an entry the description of a book is taken from. Either falls back to the
+/// other when the entry doesn't have it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DescriptionSource {
+    /// The `<summary>`.
+    Summary,
+    /// The `<content>`.
+    Content,
+    /// Whichever of them is plain text, preferring the summary.
+    #[serde(alias = "prefer_text")]
+    PreferText,
+}
+
+/// Which identifier of an entry is given to Plato as the `identifier` of its document. The
+/// entry's id is used when it doesn't have the chosen one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdentifierSource {
+    /// The entry's `<id>` as is, i.e. `urn:uuid:56e99d4d-bef9-445e-8162-35aaef306006`.
+    Id,
+    /// The entry's `<id>` without its `urn:uuid:` prefix.
+    Uuid,
+    /// The ISBN given by one of the entry's `<dc:identifier>` elements, without hyphens.
+    Isbn,
+}
+
+/// Which date the `added` timestamp of synced documents is taken from.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddedTimestampSource {
+    /// The time the document was synced.
+    Now,
+    /// The date the entry was published to the catalog.
+    Published,
+    /// The date the entry was last updated in the catalog.
+    Updated,
+}
+
+/// The formats settings can be written in.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SettingsFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl SettingsFormat {
+    /// Detect the format of a settings file from its extension, defaulting to TOML.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            Some("json") => SettingsFormat::Json,
+            Some("yaml") | Some("yml") => SettingsFormat::Yaml,
+            _ => SettingsFormat::Toml,
+        }
+    }
+}
+
+/// Find the settings file to load: the first of `Settings.toml`, `Settings.json` and
+/// `Settings.yaml` that exists, falling back to `Settings.toml`.
+pub fn settings_path() -> PathBuf {
+    SETTINGS_PATHS
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(SETTINGS_PATHS[0]))
+}
+
+/// Parse and normalize settings from `s`, written in the given `format`. References to
+/// environment variables in string settings, such as `${OPDS_URL}`, are expanded.
+pub fn parse_settings(s: &str, format: SettingsFormat) -> Result<Settings, Error> {
+    let settings: Settings = match format {
+        SettingsFormat::Toml => toml::from_str(s)?,
+        SettingsFormat::Json => serde_json::from_str(s)?,
+        SettingsFormat::Yaml => serde_yaml::from_str(s)?,
+    };
+    let mut settings = expand_env_vars(settings, |name| env::var(name).ok())?;
+    apply_secrets(&mut settings)?;
+    settings.normalize()?;
+    Ok(settings)
+}
+
+/// The credentials of a server given in the secrets file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Secrets {
+    username: Option<String>,
+    password: Option<String>,
+    cookie: Option<String>,
+}
+
+/// Replace the credentials of servers with those in the file at `secrets-path`, if it's set and
+/// exists. Secrets for servers that aren't configured are an error, as they're most likely a typo.
+fn apply_secrets(settings: &mut Settings) -> Result<(), Error> {
+    let Some(path) = &settings.secrets_path else {
+        return Ok(());
+    };
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(
+                Error::from(err).context(format!("can't read secrets file {}", path.display()))
+            )
+        }
+    };
+    let secrets: HashMap<String, Secrets> = match SettingsFormat::from_path(path) {
+        SettingsFormat::Toml => toml::from_str(&s).map_err(Error::from),
+        SettingsFormat::Json => serde_json::from_str(&s).map_err(Error::from),
+        SettingsFormat::Yaml => serde_yaml::from_str(&s).map_err(Error::from),
+    }
+    .with_context(|| format!("can't load secrets from {}", path.display()))?;
+
+    for (name, secrets) in secrets {
+        let instance = settings.servers.get_mut(&name).ok_or_else(|| {
+            format_err!(
+                "{} has secrets for '{}', which isn't a configured server",
+                path.display(),
+                name
+            )
+        })?;
+        if secrets.username.is_some() {
+            instance.username = secrets.username;
+        }
+        if secrets.password.is_some() {
+            instance.password = secrets.password;
+        }
+        if secrets.cookie.is_some() {
+            instance.cookie = secrets.cookie;
+        }
+    }
+    Ok(())
+}
+
+/// The settings holding shell commands, which are left for the shell to expand `${VAR}` in.
+const SHELL_COMMAND_SETTINGS: [&str; 3] = [
+    "pre-sync-command",
+    "post-sync-command",
+    "after-download-command",
+];
+
+/// Expand the `${VAR}` references in every string setting of `settings`, including those of
+/// servers, looking each variable up with `lookup`. Shell commands are left as they are, so they
+/// can use the shell's own `${VAR}`.
+fn expand_env_vars(
+    settings: Settings,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<Settings, Error> {
+    fn expand_value(
+        value: &mut serde_json::Value,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<(), Error> {
+        match value {
+            serde_json::Value::String(s) => *s = expand_vars(s, lookup)?,
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    expand_value(value, lookup)?;
+                }
+            }
+            serde_json::Value::Object(values) => {
+                for value in values.values_mut() {
+                    expand_value(value, lookup)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    let mut value = serde_json::to_value(settings)?;
+    if let serde_json::Value::Object(values) = &mut value {
+        for (key, value) in values.iter_mut() {
+            if !SHELL_COMMAND_SETTINGS.contains(&key.as_str()) {
+                expand_value(value, &lookup)?;
+            }
+        }
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Expand the `${VAR}` references in `s`, looking each variable up with `lookup`. `$${` is
+/// written as a literal `${`, and any other `$` is left as is.
+fn expand_vars(s: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format_err!("unterminated environment variable in '{}'", s))?;
+            let name = &reference[..end];
+            let value = lookup(name)
+                .ok_or_else(|| format_err!("environment variable {} is not set", name))?;
+            expanded.push_str(&value);
+            rest = &reference[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Load the settings from `path`, detecting the format from its extension.
+pub fn load_settings<P: AsRef<Path>>(path: P) -> Result<Settings, Error> {
+    let path = path.as_ref();
+    let s =
+        fs::read_to_string(path).with_context(|| format!("can't read file {}", path.display()))?;
+    parse_settings(&s, SettingsFormat::from_path(path))
+        .with_context(|| format!("can't load settings from {}", path.display()))
+}
+
+/// Generate a default settings file, with every setting that has a default value set to it and
+/// a brief comment describing it. Generated from [Settings::default] so it can't drift from the
+/// defaults that are actually used.
+pub fn default_config() -> Result<String, Error> {
+    let toml = toml::to_string(&Settings::default())?;
+
+    let mut config = String::from(
+        "# Default plato-opds settings. Settings without a default value, such as \
+         `pre-sync-command`,\n# are left out.\n\n",
+    );
+    for line in toml.lines() {
+        let key = line
+            .trim_start_matches('[')
+            .split([' ', '=', ']', '.'])
+            .next()
+            .unwrap_or_default();
+        let is_top_level = !key.is_empty() && !line.starts_with(' ');
+        let description = SETTING_DESCRIPTIONS
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, description)| description);
+
+        if let (true, Some(description)) = (is_top_level, description) {
+            config.push_str(&format!("# {}\n", description));
+        }
+        config.push_str(line);
+        config.push('\n');
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that organization directories that are never used and downloaded file types without
+    /// one are warned about, but not for the default settings.
+    #[test]
+    fn organization_warnings() {
+        assert!(Settings::default().warnings().is_empty());
+
+        let mut settings = Settings {
+            preferred_file_types: vec!["epub".to_string(), "cbr".to_string()],
+            ..Default::default()
+        };
+        settings
+            .organization
+            .insert("mobi".to_string(), "Kindle".to_string());
+        settings
+            .mime_overrides
+            .insert("text/plain".to_string(), "image/x-unknown".to_string());
+        settings.normalize().unwrap();
+
+        let warnings = settings.warnings();
+        assert_eq!(warnings.len(), 5, "{:?}", warnings);
+        for extension in ["cbz", "mobi", "pdf"] {
+            assert!(warnings.iter().any(|warning| warning
+                .starts_with(&format!("organization has a directory for {} ", extension))));
+        }
+        for extension in ["bin", "cbr"] {
+            assert!(
+                warnings
+                    .iter()
+                    .any(|warning| warning
+                        .starts_with(&format!("{} files are downloaded", extension)))
+            );
+        }
+
+        settings.organize_by_file_type = false;
+        assert!(settings.warnings().is_empty());
+    }
+
+    /// Test that short file type names are resolved to MIME types.
+    #[test]
+    fn normalize_preferred_file_types() {
+        let mut settings = Settings {
+            preferred_file_types: vec![
+                "epub".to_string(),
+                "PDF".to_string(),
+                "application/x-cbz".to_string(),
+            ],
+            ..Default::default()
+        };
+        settings.normalize().unwrap();
+        assert_eq!(
+            settings.preferred_file_types,
+            [
+                "application/epub+zip",
+                "application/pdf",
+                "application/x-cbz"
+            ]
+        );
+
+        let mut settings = Settings {
+            preferred_file_types: vec!["xyz".to_string()],
+            ..Default::default()
+        };
+        let err = settings.normalize().unwrap_err();
+        assert!(err.to_string().contains("xyz"));
+    }
+
+    /// Test that environment variable references are expanded in strings.
+    #[test]
+    fn expand_environment_variables() {
+        let lookup = |name: &str| match name {
+            "OPDS_URL" => Some("https://books.example.net/opds".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let expand = |s: &str| expand_vars(s, &lookup);
+
+        assert_eq!(
+            expand("${OPDS_URL}/new").unwrap(),
+            "https://books.example.net/opds/new"
+        );
+        assert_eq!(expand("a${EMPTY}b").unwrap(), "ab");
+        assert_eq!(expand("pa$$word $5").unwrap(), "pa$$word $5");
+        assert_eq!(expand("$${OPDS_URL}").unwrap(), "${OPDS_URL}");
+        assert!(expand("${MISSING}")
+            .unwrap_err()
+            .to_string()
+            .contains("MISSING"));
+        assert!(expand("${OPDS_URL").is_err());
+    }
+
+    /// Test that credentials in the secrets file replace those of servers, and that bad secrets
+    /// files are errors naming the file.
+    #[test]
+    fn settings_secrets_file() {
+        let dir = env::temp_dir().join(format!("plato-opds-secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let settings = |secrets_path: &Path| {
+            format!(
+                r#"
+                secrets-path = "{}"
+
+                [servers]
+                library = {{ url = "https://books.example.net/opds", username = "user", password = "public" }}
+                "#,
+                secrets_path.display()
+            )
+        };
+
+        // A missing secrets file leaves the settings as they are.
+        let missing = dir.join("Missing.toml");
+        let parsed = parse_settings(&settings(&missing), SettingsFormat::Toml).unwrap();
+        assert_eq!(
+            parsed.servers["library"].password.as_deref(),
+            Some("public")
+        );
+
+        let toml = dir.join("Secrets.toml");
+        fs::write(&toml, "[library]\npassword = \"hunter2\"\n").unwrap();
+        let parsed = parse_settings(&settings(&toml), SettingsFormat::Toml).unwrap();
+        let library = &parsed.servers["library"];
+        assert_eq!(library.username.as_deref(), Some("user"));
+        assert_eq!(library.password.as_deref(), Some("hunter2"));
+
+        let json = dir.join("Secrets.json");
+        fs::write(
+            &json,
+            r#"{ "library": { "username": "admin", "cookie": "session=1" } }"#,
+        )
+        .unwrap();
+        let parsed = parse_settings(&settings(&json), SettingsFormat::Toml).unwrap();
+        let library = &parsed.servers["library"];
+        assert_eq!(library.username.as_deref(), Some("admin"));
+        assert_eq!(library.password.as_deref(), Some("public"));
+        assert_eq!(library.cookie.as_deref(), Some("session=1"));
+
+        // Malformed files, unknown fields and unknown servers are errors.
+        for secrets in [
+            "[library\npassword = ",
+            "[library]\ntoken = \"abc\"\n",
+            "[libary]\npassword = \"hunter2\"\n",
+        ] {
+            fs::write(&toml, secrets).unwrap();
+            let err = parse_settings(&settings(&toml), SettingsFormat::Toml).unwrap_err();
+            assert!(format!("{:#}", err).contains("Secrets.toml"), "{:#}", err);
+        }
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    /// Test that environment variables are expanded in the servers and every other string
+    /// setting except shell commands.
+    #[test]
+    fn settings_environment_variables() {
+        env::set_var("PLATO_OPDS_TEST_PASSWORD", "hunter2");
+        let settings = parse_settings(
+            r#"
+            staging-path = "/tmp/${PLATO_OPDS_TEST_PASSWORD}"
+
+            [servers]
+            example = { url = "https://books.example.net/opds", password = "${PLATO_OPDS_TEST_PASSWORD}" }
+            "#,
+            SettingsFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.servers["example"].password.as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(settings.staging_path, Some(PathBuf::from("/tmp/hunter2")));
+
+        let err = parse_settings(
+            r#"staging-path = "${PLATO_OPDS_TEST_UNSET}""#,
+            SettingsFormat::Toml,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("PLATO_OPDS_TEST_UNSET"));
+
+        let settings = parse_settings(
+            r#"
+            pre-sync-command = "mount ${PLATO_OPDS_TEST_UNSET}"
+            post-sync-command = "umount ${PLATO_OPDS_TEST_PASSWORD}"
+            after-download-command = "epubcheck {path} > ${HOME}/epubcheck.log"
+            "#,
+            SettingsFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.pre_sync_command.as_deref(),
+            Some("mount ${PLATO_OPDS_TEST_UNSET}")
+        );
+        assert_eq!(
+            settings.post_sync_command.as_deref(),
+            Some("umount ${PLATO_OPDS_TEST_PASSWORD}")
+        );
+        assert_eq!(
+            settings.after_download_command.as_deref(),
+            Some("epubcheck {path} > ${HOME}/epubcheck.log")
+        );
+    }
+
+    /// Test that file name limits too short for a UUID file name are rejected.
+    #[test]
+    fn normalize_file_name_max_length() {
+        let parse = |max_length: usize, extension: &str| {
+            let toml = format!(
+                "file-name-max-length = {}\ndefault-extension-for-unknown = \"{}\"",
+                max_length, extension
+            );
+            parse_settings(&toml, SettingsFormat::Toml)
+        };
+
+        // The longest known extension is `audiobook`.
+        assert!(parse(46, "bin").is_ok());
+        let err = parse(45, "bin").unwrap_err();
+        assert!(err.to_string().contains("at least 46"), "{}", err);
+        assert!(parse(46, "longextension").is_err());
+    }
+
+    /// Test that server URLs are trimmed and checked when the settings are loaded.
+    #[test]
+    fn normalize_server_urls() {
+        let settings = parse_settings(
+            r#"servers.example = { url = " https://books.example.net/opds " }"#,
+            SettingsFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.servers["example"].url,
+            "https://books.example.net/opds"
+        );
+
+        let err = parse_settings(
+            r#"servers.example = { url = "books.example.net/opds" }"#,
+            SettingsFormat::Toml,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid url for server 'example'"));
+
+        let server = |credentials: &str| {
+            parse_settings(
+                &format!(
+                    r#"servers.example = {{ url = "https://books.example.net/opds", {} }}"#,
+                    credentials
+                ),
+                SettingsFormat::Toml,
+            )
+        };
+        assert!(server(r#"username = "reader", password = "p:ss""#).is_ok());
+        let err = server(r#"username = "domain:reader""#).unwrap_err();
+        assert!(err.to_string().contains("can't contain ':'"));
+        assert!(server(r#"username = "domain:reader", login = { url = "/login" }"#).is_ok());
+    }
+
+    /// Test that the format of a settings file is detected from its extension.
+    #[test]
+    fn detect_settings_format() {
+        assert_eq!(
+            SettingsFormat::from_path("Settings.toml"),
+            SettingsFormat::Toml
+        );
+        assert_eq!(
+            SettingsFormat::from_path("Settings.json"),
+            SettingsFormat::Json
+        );
+        assert_eq!(
+            SettingsFormat::from_path("Settings.YAML"),
+            SettingsFormat::Yaml
+        );
+        assert_eq!(
+            SettingsFormat::from_path("Settings.yml"),
+            SettingsFormat::Yaml
+        );
+        assert_eq!(SettingsFormat::from_path("Settings"), SettingsFormat::Toml);
+    }
+
+    /// Test that the same settings can be loaded from every supported format.
+    #[test]
+    fn parse_settings_formats() {
+        let toml = r#"
+            preferred-file-types = ["epub", "application/pdf"]
+            use-server-name-directories = false
+
+            [servers]
+            library = { url = "https://books.example.net/opds/new", username = "user" }
+        "#;
+        let json = r#"{
+            "preferred-file-types": ["epub", "application/pdf"],
+            "use-server-name-directories": false,
+            "servers": {
+                "library": { "url": "https://books.example.net/opds/new", "username": "user" }
+            }
+        }"#;
+        let yaml = r#"
+preferred-file-types: [epub, application/pdf]
+use-server-name-directories: false
+servers:
+  library:
+    url: https://books.example.net/opds/new
+    username: user
+"#;
+
+        for (s, format) in [
+            (toml, SettingsFormat::Toml),
+            (json, SettingsFormat::Json),
+            (yaml, SettingsFormat::Yaml),
+        ] {
+            let settings = parse_settings(s, format).unwrap();
+            assert_eq!(
+                settings.preferred_file_types,
+                ["application/epub+zip", "application/pdf"]
+            );
+            assert!(!settings.use_server_name_directories);
+            assert!(settings.organize_by_file_type);
+
+            let library = &settings.servers["library"];
+            assert_eq!(library.url, "https://books.example.net/opds/new");
+            assert_eq!(library.username.as_deref(), Some("user"));
+        }
+    }
+
+    /// Test that the default config parses back into the default settings and describes every
+    /// setting it contains.
+    #[test]
+    fn default_config_round_trip() {
+        let config = default_config().unwrap();
+        let settings = parse_settings(&config, SettingsFormat::Toml).unwrap();
+        let defaults = Settings::default();
+        assert_eq!(settings.preferred_file_types, defaults.preferred_file_types);
+        assert_eq!(settings.organization, defaults.organization);
+        assert_eq!(settings.max_redirects, defaults.max_redirects);
+
+        let table: toml::Table = toml::from_str(&config).unwrap();
+        for key in table.keys() {
+            assert!(
+                SETTING_DESCRIPTIONS.iter().any(|(name, _)| name == key),
+                "{} isn't described",
+                key
+            );
+        }
+    }
+
+    /// Test that every setting, including those without a default value, has a description and
+    /// every description is of a setting.
+    #[test]
+    fn setting_descriptions() {
+        let value = serde_json::to_value(Settings::default()).unwrap();
+        let keys = value.as_object().unwrap();
+        for key in keys.keys() {
+            assert!(
+                SETTING_DESCRIPTIONS.iter().any(|(name, _)| name == key),
+                "{} isn't described",
+                key
+            );
+        }
+        for (name, _) in SETTING_DESCRIPTIONS {
+            assert!(keys.contains_key(*name), "{} isn't a setting", name);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Persistent state carried between runs, used to skip work that was already done.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::SyncError;
+
+/// Holds the state of every server that has been synced.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct State {
+    /// Mapping of server names to the state recorded for them on the last successful sync.
+    pub servers: HashMap<String, ServerState>,
+}
+
+/// The state recorded for a single server.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ServerState {
+    /// The feed's `<updated>` timestamp as of the last successful sync.
+    pub feed_updated: Option<DateTime<Utc>>,
+    /// The identifiers of entries that were added to the library marked as finished.
+    pub finished: HashSet<String>,
+    /// Mapping of entry identifiers to the path their document was last added to the library
+    /// at, used to move documents whose path changes instead of adding them again.
+    pub documents: HashMap<String, PathBuf>,
+    /// The URL of the feed page the last sync stopped on, when it didn't get through every page.
+    /// The next sync resumes crawling from it.
+    pub crawl_cursor: Option<String>,
+    /// The URL of the server the crawl cursor was recorded for, so a cursor into an old feed
+    /// isn't resumed from once the server's URL changes.
+    pub crawl_cursor_url: Option<String>,
+    /// Mapping of entry identifiers to when their document last failed to download, used to skip
+    /// entries with dead links until `failed-retry-cooldown-hours` have passed.
+    pub failed: HashMap<String, DateTime<Utc>>,
+}
+
+impl ServerState {
+    /// The page to resume crawling the feed at `url` from, if the last sync stopped partway
+    /// through that same feed.
+    pub fn crawl_cursor(&self, url: &str) -> Option<&str> {
+        self.crawl_cursor
+            .as_deref()
+            .filter(|_| self.crawl_cursor_url.as_deref() == Some(url))
+    }
+
+    /// Record the page to resume crawling the feed at `url` from, or clear it with `None`.
+    pub fn set_crawl_cursor(&mut self, url: &str, cursor: Option<String>) {
+        self.crawl_cursor_url = cursor.as_ref().map(|_| url.to_string());
+        self.crawl_cursor = cursor;
+    }
+
+    /// The identifiers of entries whose document failed to download less than `cooldown_hours`
+    /// before `now`.
+    pub fn recently_failed(&self, cooldown_hours: u64, now: DateTime<Utc>) -> HashSet<String> {
+        self.failed
+            .iter()
+            .filter(|(_, failed_at)| in_cooldown(**failed_at, cooldown_hours, now))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Forget the failures whose cooldown is over, so they're retried and the state doesn't grow
+    /// with entries that have since left the feed.
+    pub fn prune_failed(&mut self, cooldown_hours: u64, now: DateTime<Utc>) {
+        self.failed
+            .retain(|_, failed_at| in_cooldown(*failed_at, cooldown_hours, now));
+    }
+}
+
+/// Whether less than `cooldown_hours` have passed between `failed_at` and `now`.
+fn in_cooldown(failed_at: DateTime<Utc>, cooldown_hours: u64, now: DateTime<Utc>) -> bool {
+    let cooldown_hours = i64::try_from(cooldown_hours).unwrap_or(i64::MAX);
+    now.signed_duration_since(failed_at).num_hours() < cooldown_hours
+}
+
+impl State {
+    /// Load the state from `path`, falling back to an empty state if the file doesn't exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SyncError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        let s = fs::read_to_string(path)
+            .map_err(|err| SyncError::io(format!("can't read file {}", path.display()), err))?;
+        serde_json::from_str(&s)
+            .map_err(|err| SyncError::parse(format!("JSON content from {}", path.display()), err))
+    }
+
+    /// Write the state to `path`, replacing any previous contents.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SyncError> {
+        let path = path.as_ref();
+        let s = serde_json::to_string_pretty(self)
+            .map_err(io::Error::from)
+            .map_err(|err| {
+                SyncError::io(format!("can't serialize state for {}", path.display()), err)
+            })?;
+        fs::write(path, s)
+            .map_err(|err| SyncError::io(format!("can't write file {}", path.display()), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that a crawl cursor is only resumed from for the URL it was recorded for.
+    #[test]
+    fn crawl_cursor_url() {
+        let url = "https://books.example.net/opds/new";
+        let cursor = "https://books.example.net/opds/new?page=3";
+        let mut server = ServerState::default();
+        assert_eq!(server.crawl_cursor(url), None);
+
+        server.set_crawl_cursor(url, Some(cursor.to_string()));
+        assert_eq!(server.crawl_cursor(url), Some(cursor));
+        assert_eq!(
+            server.crawl_cursor("https://books.example.net/opds/shelf/2"),
+            None
+        );
+
+        // Cursors recorded before their URL was stored aren't trusted.
+        server.crawl_cursor_url = None;
+        assert_eq!(server.crawl_cursor(url), None);
+
+        server.set_crawl_cursor(url, None);
+        assert_eq!(server.crawl_cursor, None);
+        assert_eq!(server.crawl_cursor_url, None);
+    }
+
+    /// Test that failed entries are only skipped, and kept in the state, until their cooldown is
+    /// over.
+    #[test]
+    fn failed_cooldown() {
+        let now = Utc::now();
+        let mut server = ServerState::default();
+        server
+            .failed
+            .insert("recent".into(), now - chrono::Duration::hours(2));
+        server
+            .failed
+            .insert("old".into(), now - chrono::Duration::hours(30));
+
+        assert_eq!(
+            server.recently_failed(24, now),
+            HashSet::from(["recent".to_string()])
+        );
+        assert_eq!(server.recently_failed(48, now).len(), 2);
+        assert!(server.recently_failed(0, now).is_empty());
+        assert!(server.recently_failed(u64::MAX, now).contains("old"));
+
+        server.prune_failed(24, now);
+        assert!(server.failed.contains_key("recent"));
+        assert!(!server.failed.contains_key("old"));
+        server.prune_failed(0, now);
+        assert!(server.failed.is_empty());
+    }
+}
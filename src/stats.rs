@@ -0,0 +1,139 @@
+//! Statistics about a sync, written as JSON with `--stats` for monitoring.
+
+use std::{collections::BTreeMap, fs, io, path::Path, time::Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::error::SyncError;
+
+/// Statistics about a whole sync.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Stats {
+    /// When the sync started.
+    pub started: DateTime<Utc>,
+    /// How long the sync took, in seconds.
+    pub duration_secs: f64,
+    /// The error that stopped the sync, if it failed.
+    pub error: Option<String>,
+    /// Mapping of server names to statistics about syncing them.
+    pub servers: BTreeMap<String, ServerStats>,
+}
+
+/// Statistics about syncing a single server.
+#[derive(Default, Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServerStats {
+    /// How many entries the server's feed has across every page.
+    pub entries: usize,
+    /// How many documents were downloaded.
+    pub downloaded: usize,
+    /// How many entries weren't downloaded, because they were already downloaded, had no usable
+    /// acquisition link, or were left out by the settings.
+    pub skipped: usize,
+    /// How many documents were moved to a new path rather than downloaded again.
+    pub moved: usize,
+    /// How many documents were left to download on a later sync, because of a download limit, the
+    /// time budget running out, the sync being stopped or the disk filling up. Documents that
+    /// already exist don't count.
+    pub remaining: usize,
+    /// How many documents failed to download or to be moved into the library.
+    pub errors: usize,
+    /// How many bytes were downloaded.
+    pub bytes: u64,
+    /// How long syncing the server took, in seconds.
+    pub duration_secs: f64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Utc::now(),
+            duration_secs: 0.0,
+            error: None,
+            servers: BTreeMap::new(),
+        }
+    }
+}
+
+impl ServerStats {
+    /// Record that syncing the server, which began at `started`, is done.
+    pub fn finish(&mut self, started: Instant) {
+        self.duration_secs = started.elapsed().as_secs_f64();
+    }
+}
+
+impl Stats {
+    /// Write the statistics as JSON to `path`, or to stdout if `path` is `-`. The JSON is written
+    /// on a single line to stdout, so it doesn't break up the stream of events Plato reads.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), SyncError> {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            println!("{}", serde_json::json!(self));
+            return Ok(());
+        }
+
+        let s = serde_json::to_string_pretty(self)
+            .map_err(io::Error::from)
+            .map_err(|err| {
+                SyncError::io(format!("can't serialize stats for {}", path.display()), err)
+            })?;
+        fs::write(path, s)
+            .map_err(|err| SyncError::io(format!("can't write file {}", path.display()), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that stats are serialized with kebab-case keys, ordered by server name.
+    #[test]
+    fn stats_json() {
+        let mut stats = Stats::default();
+        stats.servers.insert(
+            "b".to_string(),
+            ServerStats {
+                entries: 3,
+                downloaded: 1,
+                skipped: 1,
+                errors: 1,
+                ..Default::default()
+            },
+        );
+        stats
+            .servers
+            .insert("a".to_string(), ServerStats::default());
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["error"], serde_json::Value::Null);
+        assert_eq!(json["servers"]["b"]["downloaded"], 1);
+        assert_eq!(json["servers"]["b"]["duration-secs"], 0.0);
+        let names: Vec<_> = json["servers"].as_object().unwrap().keys().collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    /// Test that finishing keeps the counts made during the sync, so moved documents and entries
+    /// left for a later sync aren't taken for skipped ones.
+    #[test]
+    fn server_stats_finish() {
+        let mut stats = ServerStats {
+            entries: 10,
+            downloaded: 4,
+            skipped: 2,
+            moved: 1,
+            remaining: 2,
+            errors: 1,
+            ..Default::default()
+        };
+        stats.finish(Instant::now());
+        assert_eq!(stats.skipped, 2);
+        assert_eq!(stats.moved, 1);
+        assert_eq!(stats.remaining, 2);
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["moved"], 1);
+        assert_eq!(json["remaining"], 2);
+    }
+}